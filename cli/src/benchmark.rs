@@ -0,0 +1,39 @@
+use crate::opts::BenchmarkOpt;
+use bryggio_lib::control::benchmark::{self, BenchmarkConfig};
+use std::fs;
+
+pub fn benchmark(opt: &BenchmarkOpt) {
+    let config = fs::read_to_string(&opt.config).unwrap_or_else(|err| {
+        panic!(
+            "Error reading benchmark config '{}': {}",
+            opt.config.to_string_lossy(),
+            err
+        )
+    });
+    let config: BenchmarkConfig = serde_json::from_str(&config).unwrap_or_else(|err| {
+        panic!(
+            "Error parsing benchmark config '{}': {}",
+            opt.config.to_string_lossy(),
+            err
+        )
+    });
+    let result = benchmark::run(&config).unwrap_or_else(|err| panic!("Benchmark error: {}", err));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&result.metrics).expect("Metrics serialization error")
+    );
+    if let Some(path) = &opt.samples {
+        let samples: String = result
+            .samples
+            .iter()
+            .map(|sample| format!("{},{},{}\n", sample.time, sample.measurement, sample.signal))
+            .collect();
+        fs::write(path, samples).unwrap_or_else(|err| {
+            panic!(
+                "Error writing samples to '{}': {}",
+                path.to_string_lossy(),
+                err
+            )
+        });
+    }
+}
@@ -4,6 +4,7 @@ use serde::Serialize;
 use std::io::Write;
 use url::Url;
 
+pub mod benchmark;
 pub mod brewery;
 pub mod install;
 pub mod opts;
@@ -1,6 +1,6 @@
 #![forbid(unsafe_code)]
 use bryggio_cli::opts::{InstallTarget, Opt};
-use bryggio_cli::{brewery, install, rbpi};
+use bryggio_cli::{benchmark, brewery, install, rbpi};
 use bryggio_lib::{
     control::ControllerConfig,
     pub_sub::nats_client::NatsClient,
@@ -25,6 +25,9 @@ fn run_subcommand(opt: Opt) {
         Opt::RbPiSetup(opt) => {
             rbpi::setup(&opt);
         }
+        Opt::Benchmark(opt) => {
+            benchmark::benchmark(&opt);
+        }
         Opt::Test(opt) => {
             let config = SupervisorConfig::try_new(&opt.config).unwrap_or_else(|err| {
                 panic!(
@@ -20,6 +20,9 @@ pub enum Opt {
     ///Test script, switching controllers.
     #[structopt(name = "test")]
     Test(PubSubOpt),
+    ///Benchmark a controller offline, against a plant model or recorded measurements.
+    #[structopt(name = "benchmark")]
+    Benchmark(BenchmarkOpt),
 }
 
 impl Opt {
@@ -30,6 +33,7 @@ impl Opt {
             Self::Install(target) => target.verbose(),
             Self::RbPiSetup(opt) => opt.common.verbose,
             Self::Test(_opt) => true,
+            Self::Benchmark(opt) => opt.common.verbose,
         }
     }
 }
@@ -46,6 +50,18 @@ pub struct PubSubOpt {
     pub(crate) common: Common,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct BenchmarkOpt {
+    /// Benchmark config file
+    #[structopt(long)]
+    pub config: PathBuf,
+    /// Write the samples as `time,measurement,signal` lines to this file
+    #[structopt(long)]
+    pub samples: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub(crate) common: Common,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum InstallTarget {
     /// Install `bryggio-supervisor`
@@ -0,0 +1,31 @@
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
+
+/// Actor which only keeps its signal, used in place of hardware which must not be touched.
+#[derive(Default)]
+pub struct DummyActor {
+    signal: f32,
+}
+
+impl Actor for DummyActor {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        if (SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&signal) {
+            Ok(())
+        } else {
+            Err(ActorError::InvalidSignal {
+                signal,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
+            })
+        }
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.validate_signal(signal)?;
+        self.signal = signal;
+        Ok(())
+    }
+
+    fn effective_signal(&self) -> Option<f32> {
+        Some(self.signal)
+    }
+}
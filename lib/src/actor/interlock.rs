@@ -0,0 +1,295 @@
+//! Interlocks and permissive conditions between actors
+//!
+//! Interlocks are declared in the supervisor config and enforced by each actor client,
+//! from the published signals of other actors and the measurements of sensors.
+use crate::actor::pub_sub::SignalMsg;
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND};
+use crate::pub_sub::{ClientId, Subject};
+use crate::sensor::SensorMsg;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InterlockConfig {
+    /// `actor_id` may only be on while all `conditions` hold.
+    #[serde(rename = "permissive")]
+    Permissive {
+        actor_id: ClientId,
+        conditions: Vec<Condition>,
+    },
+    /// At most one of `actor_ids` may be on. If several are, the first in the list wins.
+    #[serde(rename = "exclusive")]
+    Exclusive { actor_ids: Vec<ClientId> },
+}
+
+impl InterlockConfig {
+    /// Whether `a` and `b` are kept from being on at once.
+    pub fn excludes(&self, a: &ClientId, b: &ClientId) -> bool {
+        match self {
+            InterlockConfig::Permissive { .. } => false,
+            InterlockConfig::Exclusive { actor_ids } => {
+                actor_ids.contains(a) && actor_ids.contains(b)
+            }
+        }
+    }
+
+    fn involves(&self, id: &ClientId) -> bool {
+        match self {
+            InterlockConfig::Permissive { actor_id, .. } => actor_id == id,
+            InterlockConfig::Exclusive { actor_ids } => actor_ids.contains(id),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Condition {
+    #[serde(rename = "actor_on")]
+    ActorOn(ClientId),
+    #[serde(rename = "sensor_above")]
+    SensorAbove { sensor_id: ClientId, value: f32 },
+    #[serde(rename = "sensor_below")]
+    SensorBelow { sensor_id: ClientId, value: f32 },
+}
+
+impl Condition {
+    /// Description of the condition if it does not hold.
+    /// Unknown signals and measurements never satisfy a condition.
+    fn violation(
+        &self,
+        actor_signals: &HashMap<ClientId, f32>,
+        measurements: &HashMap<ClientId, f32>,
+    ) -> Option<String> {
+        match self {
+            Condition::ActorOn(actor_id) => match actor_signals.get(actor_id) {
+                Some(signal) if *signal > 0.0 => None,
+                _ => Some(format!("actor '{}' is not on", actor_id)),
+            },
+            Condition::SensorAbove { sensor_id, value } => match measurements.get(sensor_id) {
+                Some(meas) if meas > value => None,
+                _ => Some(format!("sensor '{}' is not above {}", sensor_id, value)),
+            },
+            Condition::SensorBelow { sensor_id, value } => match measurements.get(sensor_id) {
+                Some(meas) if meas < value => None,
+                _ => Some(format!("sensor '{}' is not below {}", sensor_id, value)),
+            },
+        }
+    }
+}
+
+/// Interlocks of a single actor, with the latest state of the clients they depend on.
+pub struct Interlocks {
+    id: ClientId,
+    configs: Vec<InterlockConfig>,
+    actor_signals: HashMap<ClientId, f32>,
+    measurements: HashMap<ClientId, f32>,
+}
+
+impl Interlocks {
+    pub fn new(id: &ClientId, configs: &[InterlockConfig]) -> Self {
+        Interlocks {
+            id: id.clone(),
+            configs: configs
+                .iter()
+                .filter(|config| config.involves(id))
+                .cloned()
+                .collect(),
+            actor_signals: HashMap::new(),
+            measurements: HashMap::new(),
+        }
+    }
+
+    /// Subjects with the signals and measurements needed to evaluate the interlocks.
+    pub fn subjects(&self) -> Vec<Subject> {
+        let mut subjects = Vec::new();
+        for config in &self.configs {
+            match config {
+                InterlockConfig::Permissive { conditions, .. } => {
+                    for condition in conditions {
+                        subjects.push(match condition {
+                            Condition::ActorOn(actor_id) => signal_subject(actor_id),
+                            Condition::SensorAbove { sensor_id, .. }
+                            | Condition::SensorBelow { sensor_id, .. } => {
+                                SensorMsg::subject(sensor_id)
+                            }
+                        });
+                    }
+                }
+                InterlockConfig::Exclusive { actor_ids } => subjects.extend(
+                    actor_ids
+                        .iter()
+                        .filter(|actor_id| **actor_id != self.id)
+                        .map(signal_subject),
+                ),
+            }
+        }
+        subjects.sort_by(|a, b| a.0.cmp(&b.0));
+        subjects.dedup();
+        subjects
+    }
+
+    pub fn update_signal(&mut self, msg: SignalMsg) {
+        self.actor_signals.insert(msg.id, msg.signal);
+    }
+
+    pub fn update_measurement(&mut self, msg: SensorMsg) {
+        match msg.meas {
+            Ok(meas) => self.measurements.insert(msg.id, meas),
+            Err(_) => self.measurements.remove(&msg.id),
+        };
+    }
+
+    /// Set `signal`, or switch the actor off if it violates an interlock, returning the reason.
+    /// Switching off bypasses the signal range of the actor, as does a `forced` signal.
+    pub fn apply(
+        &self,
+        actor: &mut dyn Actor,
+        signal: f32,
+        running: bool,
+        forced: bool,
+    ) -> Result<Option<String>, ActorError> {
+        if let Some(violation) = self.violation(signal, running) {
+            actor.force_signal(SIGNAL_LOWER_BOUND)?;
+            return Ok(Some(violation));
+        }
+        if forced {
+            actor.force_signal(signal)?;
+        } else {
+            actor.set_signal(signal)?;
+        }
+        Ok(None)
+    }
+
+    /// Reason why `signal` is not allowed, if it violates an interlock.
+    ///
+    /// `running` is set when checking a signal which is already applied, in which case only
+    /// actors earlier in an exclusive group take precedence. This way, if two actors were
+    /// switched on at the same time, only one of them is switched off.
+    pub fn violation(&self, signal: f32, running: bool) -> Option<String> {
+        if signal <= 0.0 {
+            return None;
+        }
+        self.configs.iter().find_map(|config| match config {
+            InterlockConfig::Permissive { conditions, .. } => conditions
+                .iter()
+                .find_map(|condition| condition.violation(&self.actor_signals, &self.measurements)),
+            InterlockConfig::Exclusive { actor_ids } => actor_ids
+                .iter()
+                .take_while(|actor_id| !running || **actor_id != self.id)
+                .filter(|actor_id| **actor_id != self.id)
+                .find(|actor_id| self.actor_signals.get(actor_id).map_or(false, |s| *s > 0.0))
+                .map(|actor_id| format!("actor '{}' is on", actor_id)),
+        })
+    }
+}
+
+fn signal_subject(actor_id: &ClientId) -> Subject {
+    Subject(format!("actor.{}.current_signal", actor_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::dummy::DummyActor;
+    use crate::actor::{RangeLimited, SignalRange};
+    use crate::time::TimeStamp;
+
+    fn id(id: &str) -> ClientId {
+        ClientId(id.into())
+    }
+
+    fn signal(actor_id: &str, signal: f32) -> SignalMsg {
+        SignalMsg {
+            id: id(actor_id),
+            timestamp: TimeStamp(0),
+            signal,
+            moving: false,
+            unknown: false,
+            source: None,
+        }
+    }
+
+    fn configs() -> Vec<InterlockConfig> {
+        serde_json::from_str(
+            r#"
+            [
+              {"permissive": {
+                "actor_id": "boil_heater",
+                "conditions": [
+                  {"actor_on": "pump"},
+                  {"sensor_above": {"sensor_id": "boil_level", "value": 10.0}}
+                ]
+              }},
+              {"exclusive": {"actor_ids": ["heater_a", "heater_b"]}}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_permissive() {
+        let mut interlocks = Interlocks::new(&id("boil_heater"), &configs());
+        assert_eq!(
+            interlocks.subjects(),
+            vec![
+                Subject("actor.pump.current_signal".into()),
+                Subject("sensor.boil_level.measurement".into())
+            ]
+        );
+        assert!(interlocks.violation(0.0, false).is_none());
+        assert!(interlocks.violation(1.0, false).is_some());
+
+        interlocks.update_signal(signal("pump", 1.0));
+        interlocks.update_measurement(SensorMsg {
+            id: id("boil_level"),
+            timestamp: TimeStamp(0),
+            meas: Ok(12.0),
+        });
+        assert!(interlocks.violation(1.0, false).is_none());
+
+        interlocks.update_signal(signal("pump", 0.0));
+        assert!(interlocks.violation(1.0, true).is_some());
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let mut heater_a = Interlocks::new(&id("heater_a"), &configs());
+        let mut heater_b = Interlocks::new(&id("heater_b"), &configs());
+        assert!(heater_a.violation(1.0, false).is_none());
+        assert!(configs()[1].excludes(&id("heater_b"), &id("heater_a")));
+        assert!(!configs()[0].excludes(&id("boil_heater"), &id("pump")));
+
+        heater_a.update_signal(signal("heater_b", 1.0));
+        heater_b.update_signal(signal("heater_a", 1.0));
+        assert!(heater_a.violation(1.0, false).is_some());
+        assert!(heater_b.violation(1.0, false).is_some());
+        // Both on at once, only the latter one is switched off.
+        assert!(heater_a.violation(1.0, true).is_none());
+        assert!(heater_b.violation(1.0, true).is_some());
+    }
+
+    #[test]
+    fn test_apply_outside_range() {
+        let mut actor = RangeLimited {
+            actor: Box::new(DummyActor::default()),
+            range: SignalRange::try_new(0.3, 1.0).unwrap(),
+        };
+        let mut interlocks = Interlocks::new(&id("heater_a"), &configs());
+        assert_eq!(
+            interlocks.apply(&mut actor, 0.5, false, false).unwrap(),
+            None
+        );
+        assert!(interlocks.apply(&mut actor, 0.0, false, false).is_err());
+        assert_eq!(actor.effective_signal(), Some(0.5));
+
+        // Switched off on a violation, although off is outside the signal range.
+        interlocks.update_signal(signal("heater_b", 1.0));
+        assert!(interlocks
+            .apply(&mut actor, 0.5, false, false)
+            .unwrap()
+            .is_some());
+        assert_eq!(actor.effective_signal(), Some(0.0));
+
+        interlocks.update_signal(signal("heater_b", 0.0));
+        assert_eq!(interlocks.apply(&mut actor, 0.0, true, true).unwrap(), None);
+    }
+}
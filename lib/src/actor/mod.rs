@@ -1,24 +1,222 @@
-#[cfg(target_arch = "x86_64")]
-use crate::hardware::dummy as hardware_impl;
-#[cfg(target_arch = "arm")]
-use crate::hardware::rbpi as hardware_impl;
+use crate::hardware::{Devices, GpioBackend};
 use crate::pub_sub::{ClientId, PubSubError};
+use crate::time::SystemClock;
+use embedded_hal::digital::OutputPin;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
+pub mod dummy;
+pub mod interlock;
+pub mod motorized_valve;
 pub mod pub_sub;
 pub mod simple_gpio;
+pub mod stats;
+pub mod switch_times;
+pub mod sysfs_pwm;
+pub mod time_proportional;
+pub mod watchdog;
 pub use pub_sub::ActorClient;
 
+pub(crate) const SIGNAL_LOWER_BOUND: f32 = 0.0;
+pub(crate) const SIGNAL_UPPER_BOUND: f32 = 1.0;
+
 pub trait Actor: Send {
     fn validate_signal(&self, signal: f32) -> Result<(), ActorError>;
     fn set_signal(&mut self, signal: f32) -> Result<(), ActorError>;
+    /// Set a signal needed for safety, e.g. the safe signal or off on an interlock violation.
+    /// Unlike `set_signal`, this bypasses the configured signal range.
+    fn force_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.set_signal(signal)
+    }
+    /// Signal actually applied, if it differs from the one set.
+    fn effective_signal(&self) -> Option<f32> {
+        None
+    }
+    /// Whether the actor is still on its way to the signal set, e.g. a valve being opened.
+    fn is_moving(&self) -> bool {
+        false
+    }
+    /// Whether the applied signal is unknown, e.g. for a valve which did not reach its end stop.
+    fn is_unknown(&self) -> bool {
+        false
+    }
+    /// Period at which `tick` is called, for actors which switch between signals.
+    fn tick_period(&self) -> Option<Duration> {
+        None
+    }
+    fn tick(&mut self) -> Result<(), ActorError> {
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum ActorType {
     #[serde(rename = "simple_gpio")]
     SimpleGpio(u32),
+    /// Slow PWM, with the on-time of each window proportional to the signal.
+    #[serde(rename = "time_proportional")]
+    TimeProportional {
+        pin_number: u32,
+        window_ms: u64,
+        /// Min. length of both on and off pulses.
+        #[serde(default)]
+        min_pulse_ms: u64,
+    },
+    /// Hardware PWM channel of `/sys/class/pwm/pwmchip<chip>`.
+    #[serde(rename = "sysfs_pwm")]
+    SysfsPwm {
+        chip: u32,
+        channel: u32,
+        period_ns: u64,
+        /// Root of the sysfs PWM class, configurable for testing.
+        #[serde(default = "default_sysfs_pwm_root")]
+        root: PathBuf,
+    },
+    /// Pin of an MCP23017 I/O expander on `/dev/i2c-<bus>`, e.g. on a relay board.
+    #[serde(rename = "mcp23017")]
+    Mcp23017 { bus: u32, address: u8, pin: u8 },
+    /// Valve with separate open and close lines, driven for the travel time or to its end stops.
+    #[serde(rename = "motorized_valve")]
+    MotorizedValve {
+        open_pin: u32,
+        close_pin: u32,
+        travel_time_ms: u64,
+        #[serde(default)]
+        end_stops: Option<motorized_valve::EndStopsConfig>,
+    },
+}
+
+impl ActorType {
+    /// Whether the actor is either fully on or off.
+    pub fn is_on_off(&self) -> bool {
+        match self {
+            ActorType::SimpleGpio(_)
+            | ActorType::Mcp23017 { .. }
+            | ActorType::MotorizedValve { .. } => true,
+            ActorType::TimeProportional { .. } | ActorType::SysfsPwm { .. } => false,
+        }
+    }
+}
+
+fn default_sysfs_pwm_root() -> PathBuf {
+    PathBuf::from("/sys/class/pwm")
+}
+
+/// Allowed signals of an actor, within the bounds of what any actor accepts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SignalRange {
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+}
+
+impl Default for SignalRange {
+    fn default() -> Self {
+        SignalRange {
+            min: SIGNAL_LOWER_BOUND,
+            max: SIGNAL_UPPER_BOUND,
+        }
+    }
+}
+
+impl SignalRange {
+    fn try_new(min: f32, max: f32) -> Result<SignalRange, ActorError> {
+        if SIGNAL_LOWER_BOUND <= min && min <= max && max <= SIGNAL_UPPER_BOUND {
+            Ok(SignalRange { min, max })
+        } else {
+            Err(ActorError::Generic(format!(
+                "Invalid signal range [{}, {}], must be within [{}, {}]",
+                min, max, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND
+            )))
+        }
+    }
+
+    pub fn validate(&self, signal: f32) -> Result<(), ActorError> {
+        if self.min <= signal && signal <= self.max {
+            Ok(())
+        } else {
+            Err(ActorError::InvalidSignal {
+                signal,
+                lower_bound: self.min,
+                upper_bound: self.max,
+            })
+        }
+    }
+}
+
+/// Actor restricted to a narrower signal range than it supports.
+struct RangeLimited {
+    actor: Box<dyn Actor>,
+    range: SignalRange,
+}
+
+impl Actor for RangeLimited {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        self.range.validate(signal)?;
+        self.actor.validate_signal(signal)
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.range.validate(signal)?;
+        self.actor.set_signal(signal)
+    }
+
+    fn force_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.actor.force_signal(signal)
+    }
+
+    fn effective_signal(&self) -> Option<f32> {
+        self.actor.effective_signal()
+    }
+
+    fn is_moving(&self) -> bool {
+        self.actor.is_moving()
+    }
+
+    fn is_unknown(&self) -> bool {
+        self.actor.is_unknown()
+    }
+
+    fn tick_period(&self) -> Option<Duration> {
+        self.actor.tick_period()
+    }
+
+    fn tick(&mut self) -> Result<(), ActorError> {
+        self.actor.tick()
+    }
+}
+
+/// Output pin with configurable polarity, where an active-low pin is low when on.
+pub struct PolarizedPin<T: OutputPin> {
+    pin: T,
+    active_low: bool,
+}
+
+impl<T: OutputPin> PolarizedPin<T> {
+    pub fn new(pin: T, active_low: bool) -> Self {
+        PolarizedPin { pin, active_low }
+    }
+}
+
+impl<T: OutputPin> OutputPin for PolarizedPin<T> {
+    type Error = T::Error;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        if self.active_low {
+            self.pin.try_set_high()
+        } else {
+            self.pin.try_set_low()
+        }
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        if self.active_low {
+            self.pin.try_set_low()
+        } else {
+            self.pin.try_set_high()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,23 +224,185 @@ pub struct ActorConfig {
     pub id: ClientId,
     #[serde(rename = "type")]
     pub(crate) type_: ActorType,
+    /// Time without new signals, after which the safe signal is applied.
+    #[serde(default)]
+    pub(crate) watchdog_timeout_ms: Option<u64>,
+    /// Signal applied at startup and shutdown, and when the watchdog trips.
+    #[serde(default)]
+    pub(crate) safe_signal: f32,
+    /// On when the output is low, e.g. for relay boards with inverted inputs.
+    #[serde(default)]
+    pub(crate) active_low: bool,
+    #[serde(default)]
+    pub(crate) signal_range: SignalRange,
+    /// Power at full signal in W, for the energy accounting and the power budget.
+    #[serde(default)]
+    pub(crate) rated_power: Option<f32>,
+    /// Take part in the power budget, if there is one, and only apply granted signals.
+    /// Requires a rated power.
+    #[serde(default)]
+    pub(crate) power_budget: bool,
+    /// Min. time the actor is kept on once switched on, e.g. for a compressor.
+    #[serde(default)]
+    pub(crate) min_on_ms: u64,
+    /// Min. time the actor is kept off once switched off.
+    #[serde(default)]
+    pub(crate) min_off_ms: u64,
 }
 
 impl ActorConfig {
-    pub fn get_actor(&self) -> Result<Box<dyn Actor>, ActorError> {
+    /// Rated power of an actor in the power budget, `None` for actors outside of it.
+    pub fn budgeted_power(&self) -> Option<f32> {
+        self.rated_power.filter(|_| self.power_budget)
+    }
+
+    /// Physical level of a switched output at the safe signal.
+    fn safe_level(&self) -> bool {
+        (self.safe_signal > 0.0) != self.active_low
+    }
+
+    /// Expander pin of an MCP23017 actor, with the level of the pin at the safe signal.
+    pub fn mcp23017_safe_level(&self) -> Option<(u32, u8, u8, bool)> {
+        match self.type_ {
+            ActorType::Mcp23017 { bus, address, pin } => {
+                Some((bus, address, pin, self.safe_level()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Actor limited to the configured signal range, with the safe signal applied.
+    pub fn get_actor(&self, devices: &mut Devices) -> Result<Box<dyn Actor>, ActorError> {
+        if !(SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&self.safe_signal) {
+            return Err(ActorError::InvalidSignal {
+                signal: self.safe_signal,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
+            });
+        }
+        if self.power_budget && self.rated_power.is_none() {
+            return Err(ActorError::Generic(format!(
+                "Actor '{}' needs a rated power to be in the power budget",
+                self.id
+            )));
+        }
+        let range = SignalRange::try_new(self.signal_range.min, self.signal_range.max)?;
+        let mut actor = self.get_base_actor(devices)?;
+        if self.min_on_ms > 0 || self.min_off_ms > 0 {
+            actor = Box::new(switch_times::MinSwitchTimes::new(
+                actor,
+                self.min_on_ms,
+                self.min_off_ms,
+                Box::new(SystemClock),
+            ));
+        }
+        let mut actor = RangeLimited { actor, range };
+        actor.force_signal(self.safe_signal)?;
+        Ok(Box::new(actor))
+    }
+
+    /// Output pin, requested at the physical level of `high`.
+    fn get_gpio_pin(
+        &self,
+        devices: &Devices,
+        pin_number: u32,
+        high: bool,
+    ) -> Result<impl OutputPin + Send, ActorError> {
+        let gpio_pin = devices
+            .gpio()
+            .get_gpio_pin(pin_number, self.id.as_ref(), high)
+            .map_err(|err| ActorError::Generic(err.to_string()))?;
+        Ok(PolarizedPin::new(gpio_pin, self.active_low))
+    }
+
+    fn get_base_actor(&self, devices: &mut Devices) -> Result<Box<dyn Actor>, ActorError> {
         match &self.type_ {
             ActorType::SimpleGpio(pin_number) => {
-                let gpio_pin = hardware_impl::get_gpio_pin(*pin_number, &self.id.as_ref()).unwrap();
+                let gpio_pin = self.get_gpio_pin(devices, *pin_number, self.safe_level())?;
                 let actor = simple_gpio::SimpleGpioActor::try_new(self.id.as_ref(), gpio_pin)?;
                 Ok(Box::new(actor))
             }
+            ActorType::TimeProportional {
+                pin_number,
+                window_ms,
+                min_pulse_ms,
+            } => {
+                let gpio_pin = self.get_gpio_pin(devices, *pin_number, self.safe_level())?;
+                let actor = time_proportional::TimeProportionalActor::try_new(
+                    self.id.as_ref(),
+                    gpio_pin,
+                    *window_ms,
+                    *min_pulse_ms,
+                    Box::new(SystemClock),
+                )?;
+                Ok(Box::new(actor))
+            }
+            // Without real GPIO, nothing else should touch the hardware either.
+            ActorType::SysfsPwm { .. } if !devices.gpio().is_hardware() => {
+                Ok(Box::new(dummy::DummyActor::default()))
+            }
+            ActorType::SysfsPwm {
+                chip,
+                channel,
+                period_ns,
+                root,
+            } => {
+                let actor = sysfs_pwm::SysfsPwmActor::try_new(
+                    self.id.as_ref(),
+                    root,
+                    *chip,
+                    *channel,
+                    *period_ns,
+                    self.active_low,
+                )?;
+                Ok(Box::new(actor))
+            }
+            ActorType::Mcp23017 { bus, address, pin } => {
+                let pin = devices
+                    .mcp23017_pin(*bus, *address, *pin)
+                    .map_err(|err| ActorError::Generic(err.to_string()))?;
+                let pin = PolarizedPin::new(pin, self.active_low);
+                let actor = simple_gpio::SimpleGpioActor::try_new(self.id.as_ref(), pin)?;
+                Ok(Box::new(actor))
+            }
+            ActorType::MotorizedValve {
+                open_pin,
+                close_pin,
+                travel_time_ms,
+                end_stops,
+            } => {
+                let end_stops = match (end_stops, devices.gpio()) {
+                    // Dummy inputs never change, so the end stops would never be reached.
+                    (_, GpioBackend::Dummy) | (None, _) => None,
+                    (Some(config), gpio) => {
+                        let input = |pin_number| {
+                            gpio.get_input_pin(pin_number, self.id.as_ref())
+                                .map_err(|err| ActorError::Generic(err.to_string()))
+                        };
+                        Some(motorized_valve::EndStops::new(
+                            input(config.open_pin)?,
+                            input(config.closed_pin)?,
+                            config.active_low,
+                        ))
+                    }
+                };
+                let actor = motorized_valve::MotorizedValveActor::try_new(
+                    self.id.as_ref(),
+                    self.get_gpio_pin(devices, *open_pin, self.active_low)?,
+                    self.get_gpio_pin(devices, *close_pin, self.active_low)?,
+                    end_stops,
+                    *travel_time_ms,
+                    Box::new(SystemClock),
+                )?;
+                Ok(Box::new(actor))
+            }
         }
     }
 }
 
 #[derive(Error, Debug, Clone)]
 pub enum ActorError {
-    #[error("Invalid signal: {signal}, must be in ({lower_bound}, {upper_bound})")]
+    #[error("Invalid signal: {signal}, must be in [{lower_bound}, {upper_bound}]")]
     InvalidSignal {
         signal: f32,
         lower_bound: f32,
@@ -57,3 +417,108 @@ impl From<ActorError> for PubSubError {
         PubSubError::Client(format!("Actor error: '{}'", err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::mock;
+
+    struct FakePin(bool);
+
+    impl OutputPin for FakePin {
+        type Error = ();
+
+        fn try_set_low(&mut self) -> Result<(), Self::Error> {
+            self.0 = false;
+            Ok(())
+        }
+
+        fn try_set_high(&mut self) -> Result<(), Self::Error> {
+            self.0 = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_signal_range() {
+        let range = SignalRange::try_new(0.2, 0.8).unwrap();
+        assert!(range.validate(0.2).is_ok());
+        assert!(range.validate(0.8).is_ok());
+        assert!(range.validate(0.1).is_err());
+        assert!(range.validate(0.9).is_err());
+        assert!(SignalRange::try_new(0.8, 0.2).is_err());
+        assert!(SignalRange::try_new(0.0, 2.0).is_err());
+    }
+
+    #[test]
+    fn test_active_low() {
+        let mut pin = PolarizedPin::new(FakePin(false), true);
+        pin.try_set_high().unwrap();
+        assert!(!pin.pin.0);
+        pin.try_set_low().unwrap();
+        assert!(pin.pin.0);
+
+        let mut pin = PolarizedPin::new(FakePin(false), false);
+        pin.try_set_high().unwrap();
+        assert!(pin.pin.0);
+    }
+
+    #[test]
+    fn test_mock_backend() {
+        let config: ActorConfig = serde_json::from_str(
+            r#"{"id": "hlt_heater", "type": {"simple_gpio": 17}, "active_low": true}"#,
+        )
+        .unwrap();
+        let mut actor = config
+            .get_actor(&mut Devices::new(GpioBackend::Mock))
+            .unwrap();
+        // The safe signal is applied at startup.
+        assert_eq!(mock::pin_state(17), Some(true));
+        actor.set_signal(1.0).unwrap();
+        assert_eq!(mock::pin_state(17), Some(false));
+        assert!(actor.set_signal(1.5).is_err());
+
+        // The safe signal may be outside the signal range.
+        let config: ActorConfig = serde_json::from_str(
+            r#"{"id": "pump", "type": {"simple_gpio": 18}, "signal_range": {"min": 0.5, "max": 1.0}}"#,
+        )
+        .unwrap();
+        let mut actor = config
+            .get_actor(&mut Devices::new(GpioBackend::Mock))
+            .unwrap();
+        assert_eq!(mock::pin_state(18), Some(false));
+        assert!(actor.set_signal(0.0).is_err());
+
+        let config: ActorConfig = serde_json::from_str(
+            r#"{"id": "pump", "type": {"simple_gpio": 19}, "safe_signal": 1.5}"#,
+        )
+        .unwrap();
+        assert!(config
+            .get_actor(&mut Devices::new(GpioBackend::Mock))
+            .is_err());
+
+        // Pins start at the requested level, before any actor sets them.
+        GpioBackend::Mock.get_gpio_pin(20, "pump", true).unwrap();
+        assert_eq!(mock::pin_state(20), Some(true));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: ActorConfig = serde_json::from_str(
+            r#"
+            {
+              "id": "mash_heater",
+              "type": {"simple_gpio": 0},
+              "active_low": true,
+              "signal_range": {"min": 0.0, "max": 0.5}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.active_low);
+        assert_eq!(config.signal_range, SignalRange { min: 0.0, max: 0.5 });
+        let config: ActorConfig =
+            serde_json::from_str(r#"{"id": "pump", "type": {"simple_gpio": 1}}"#).unwrap();
+        assert!(!config.active_low);
+        assert_eq!(config.signal_range, SignalRange::default());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
+use crate::time::{Clock, TimeStamp};
+use embedded_hal::digital::{InputPin, OutputPin};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Resolution of the travel time and end-stop checks.
+const TICK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Limit switches signalling that the valve is fully open or closed.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct EndStopsConfig {
+    pub(crate) open_pin: u32,
+    pub(crate) closed_pin: u32,
+    /// Switches which pull the input low when reached.
+    #[serde(default)]
+    pub(crate) active_low: bool,
+}
+
+pub struct EndStops<I: InputPin + Send> {
+    open: I,
+    closed: I,
+    active_low: bool,
+}
+
+impl<I: InputPin + Send> EndStops<I> {
+    pub fn new(open: I, closed: I, active_low: bool) -> Self {
+        EndStops {
+            open,
+            closed,
+            active_low,
+        }
+    }
+
+    fn reached(&self, open: bool) -> Result<bool, ActorError> {
+        let input = if open { &self.open } else { &self.closed };
+        let high = input.try_is_high().map_err(|_err| {
+            ActorError::Generic(String::from("GPIO error when reading end stop"))
+        })?;
+        Ok(high != self.active_low)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    Unknown,
+    Open,
+    Closed,
+    Moving { open: bool, start: TimeStamp },
+}
+
+/// Valve driven by separate open and close lines, e.g. a motorized ball valve.
+///
+/// A signal above zero opens the valve and zero closes it. The motor is driven until the
+/// end stop is reached or, without end stops, for the travel time. With end stops, not
+/// reaching the end position within the travel time is an error.
+///
+/// The applied signal is the last end position reached, also while moving. After an error,
+/// or before the first end position is reached, it is unknown and reported as closed.
+pub struct MotorizedValveActor<O: OutputPin + Send, I: InputPin + Send> {
+    pub id: String,
+    open_line: O,
+    close_line: O,
+    end_stops: Option<EndStops<I>>,
+    travel_time: f32,
+    position: Position,
+    /// Last end position reached, `None` if unknown.
+    confirmed: Option<bool>,
+    clock: Box<dyn Clock>,
+}
+
+impl<O: OutputPin + Send, I: InputPin + Send> MotorizedValveActor<O, I> {
+    pub fn try_new(
+        id: &str,
+        open_line: O,
+        close_line: O,
+        end_stops: Option<EndStops<I>>,
+        travel_time_ms: u64,
+        clock: Box<dyn Clock>,
+    ) -> Result<MotorizedValveActor<O, I>, ActorError> {
+        if travel_time_ms == 0 {
+            return Err(ActorError::Generic(format!(
+                "Travel time of valve '{}' must be positive",
+                id
+            )));
+        }
+        let mut actor = MotorizedValveActor {
+            id: id.into(),
+            open_line,
+            close_line,
+            end_stops,
+            travel_time: travel_time_ms as f32 / 1000.0,
+            position: Position::Unknown,
+            confirmed: None,
+            clock,
+        };
+        actor.stop()?;
+        Ok(actor)
+    }
+
+    fn stop(&mut self) -> Result<(), ActorError> {
+        self.set_line(true, false)?;
+        self.set_line(false, false)
+    }
+
+    fn set_line(&mut self, open: bool, high: bool) -> Result<(), ActorError> {
+        let line = if open {
+            &mut self.open_line
+        } else {
+            &mut self.close_line
+        };
+        let res = if high {
+            line.try_set_high()
+        } else {
+            line.try_set_low()
+        };
+        res.map_err(|_err| ActorError::Generic(format!("GPIO error when driving '{}'", self.id)))
+    }
+
+    fn end_stop_reached(&self, open: bool) -> Result<bool, ActorError> {
+        match &self.end_stops {
+            Some(end_stops) => end_stops.reached(open),
+            None => Ok(false),
+        }
+    }
+
+    fn reach(&mut self, open: bool) {
+        self.position = if open {
+            Position::Open
+        } else {
+            Position::Closed
+        };
+        self.confirmed = Some(open);
+    }
+
+    fn target(&self) -> Option<bool> {
+        match self.position {
+            Position::Open => Some(true),
+            Position::Closed => Some(false),
+            Position::Moving { open, .. } => Some(open),
+            Position::Unknown => None,
+        }
+    }
+}
+
+impl<O: OutputPin + Send, I: InputPin + Send> Actor for MotorizedValveActor<O, I> {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        if (SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&signal) {
+            Ok(())
+        } else {
+            Err(ActorError::InvalidSignal {
+                signal,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
+            })
+        }
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.validate_signal(signal)?;
+        let open = signal > 0.0;
+        if self.target() == Some(open) {
+            return Ok(());
+        }
+        // Both lines are never driven at once, also when reversing.
+        self.stop()?;
+        if self.end_stop_reached(open)? {
+            self.reach(open);
+            return Ok(());
+        }
+        self.set_line(open, true)?;
+        self.position = Position::Moving {
+            open,
+            start: self.clock.now(),
+        };
+        Ok(())
+    }
+
+    fn effective_signal(&self) -> Option<f32> {
+        match self.confirmed {
+            Some(true) => Some(SIGNAL_UPPER_BOUND),
+            _ => Some(SIGNAL_LOWER_BOUND),
+        }
+    }
+
+    fn is_moving(&self) -> bool {
+        matches!(self.position, Position::Moving { .. })
+    }
+
+    fn is_unknown(&self) -> bool {
+        self.confirmed.is_none()
+    }
+
+    fn tick_period(&self) -> Option<Duration> {
+        Some(TICK_PERIOD)
+    }
+
+    fn tick(&mut self) -> Result<(), ActorError> {
+        let (open, start) = match self.position {
+            Position::Moving { open, start } => (open, start),
+            _ => return Ok(()),
+        };
+        if self.end_stop_reached(open)? {
+            self.stop()?;
+            self.reach(open);
+        } else if self.clock.now().secs_since(start) >= self.travel_time {
+            self.stop()?;
+            if self.end_stops.is_some() {
+                self.position = Position::Unknown;
+                self.confirmed = None;
+                return Err(ActorError::Generic(format!(
+                    "Valve '{}' not {} within {} s",
+                    self.id,
+                    if open { "open" } else { "closed" },
+                    self.travel_time
+                )));
+            }
+            self.reach(open);
+        }
+        Ok(())
+    }
+}
+
+impl<O: OutputPin + Send, I: InputPin + Send> Drop for MotorizedValveActor<O, I> {
+    fn drop(&mut self) {
+        // Best effort, there is no one left to report errors to.
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{mock, GpioBackend, GpioPin};
+    use crate::time::ManualClock;
+
+    const OPEN: u32 = 40;
+    const CLOSE: u32 = 41;
+    const OPEN_STOP: u32 = 42;
+    const CLOSED_STOP: u32 = 43;
+
+    fn pin(pin_number: u32) -> GpioPin {
+        GpioBackend::Mock
+            .get_gpio_pin(pin_number, "valve", false)
+            .unwrap()
+    }
+
+    fn lines() -> (Option<bool>, Option<bool>) {
+        (mock::pin_state(OPEN), mock::pin_state(CLOSE))
+    }
+
+    #[test]
+    fn test_end_stops() {
+        let clock = ManualClock::new(TimeStamp(0));
+        let end_stops = EndStops::new(pin(OPEN_STOP), pin(CLOSED_STOP), false);
+        let mut valve = MotorizedValveActor::try_new(
+            "valve",
+            pin(OPEN),
+            pin(CLOSE),
+            Some(end_stops),
+            5000,
+            Box::new(clock.clone()),
+        )
+        .unwrap();
+        mock::MockPin::new(CLOSED_STOP).set(true);
+
+        // Already at the end stop.
+        valve.set_signal(0.0).unwrap();
+        assert!(!valve.is_moving());
+        assert_eq!(lines(), (Some(false), Some(false)));
+
+        valve.set_signal(1.0).unwrap();
+        assert!(valve.is_moving());
+        assert_eq!(lines(), (Some(true), Some(false)));
+        // Still closed until the open end stop is reached.
+        assert_eq!(valve.effective_signal(), Some(0.0));
+        clock.advance(2.0);
+        mock::MockPin::new(CLOSED_STOP).set(false);
+        valve.tick().unwrap();
+        assert!(valve.is_moving());
+        mock::MockPin::new(OPEN_STOP).set(true);
+        valve.tick().unwrap();
+        assert!(!valve.is_moving());
+        assert_eq!(lines(), (Some(false), Some(false)));
+        assert_eq!(valve.effective_signal(), Some(1.0));
+
+        // Stuck valve.
+        valve.set_signal(0.0).unwrap();
+        assert_eq!(lines(), (Some(false), Some(true)));
+        clock.advance(4.9);
+        valve.tick().unwrap();
+        clock.advance(0.1);
+        assert!(valve.tick().is_err());
+        assert!(!valve.is_moving());
+        assert!(valve.is_unknown());
+        assert_eq!(lines(), (Some(false), Some(false)));
+    }
+
+    #[test]
+    fn test_travel_time() {
+        let clock = ManualClock::new(TimeStamp(0));
+        let mut valve: MotorizedValveActor<_, GpioPin> = MotorizedValveActor::try_new(
+            "valve",
+            pin(50),
+            pin(51),
+            None,
+            5000,
+            Box::new(clock.clone()),
+        )
+        .unwrap();
+        valve.set_signal(1.0).unwrap();
+        assert_eq!(mock::pin_state(50), Some(true));
+        clock.advance(2.0);
+        // Reversing restarts the travel time.
+        valve.set_signal(0.0).unwrap();
+        assert_eq!(mock::pin_state(50), Some(false));
+        assert_eq!(mock::pin_state(51), Some(true));
+        clock.advance(4.0);
+        valve.tick().unwrap();
+        assert!(valve.is_moving());
+        clock.advance(1.0);
+        valve.tick().unwrap();
+        assert!(!valve.is_moving());
+        assert_eq!(mock::pin_state(51), Some(false));
+        assert!(valve.set_signal(1.5).is_err());
+    }
+}
@@ -1,29 +1,314 @@
-use crate::actor::Actor;
+use crate::actor::interlock::Interlocks;
+use crate::actor::stats::Accounting;
+use crate::actor::watchdog::Watchdog;
+use crate::actor::{Actor, SIGNAL_LOWER_BOUND};
 use crate::logger::{error, info};
+use crate::power::pub_sub::PowerPubMsg;
 use crate::pub_sub::{
     nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsConfig, ClientId,
-    ClientState, PubSubClient, PubSubError, PubSubMsg, Subject,
+    ClientState, PubSubBackend, PubSubClient, PubSubError, PubSubMsg, Subject,
 };
+use crate::sensor::SensorMsg;
+use crate::supervisor::lease::Lease;
+use crate::supervisor::pub_sub::{SupervisorPubMsg, SupervisorSubMsg};
 use crate::time::TimeStamp;
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
-pub struct ActorClient {
+use std::time::{Duration, Instant};
+
+/// Max. time between checks for a kill command, for actors without a tick period.
+const POLL_PERIOD: Duration = Duration::from_millis(500);
+
+pub struct ActorClient<C: PubSubBackend = NatsClient> {
     id: ClientId,
     actor: Box<dyn Actor>,
-    /// TODO: Make generic over PubSubClient
-    client: NatsClient,
+    interlocks: Interlocks,
+    /// Subject with the signals to apply.
+    signal_subject: Subject,
+    current_signal: Option<SignalMsg>,
+    watchdog: Option<Watchdog>,
+    safe_signal: f32,
+    accounting: Accounting,
+    /// Signals from clients other than the lease holder are rejected.
+    lease: Option<Lease>,
+    lease_sub: Option<Subscription>,
+    client: C,
 }
 
 impl ActorClient {
-    pub fn new(id: ClientId, actor: Box<dyn Actor>, config: &NatsConfig) -> Self {
+    pub fn new(
+        id: ClientId,
+        actor: Box<dyn Actor>,
+        interlocks: Interlocks,
+        config: &NatsConfig,
+    ) -> Self {
         let client = NatsClient::try_new(config).unwrap();
-        ActorClient { id, actor, client }
+        ActorClient::with_backend(id, actor, interlocks, client)
+    }
+}
+
+impl<C: PubSubBackend> ActorClient<C> {
+    fn with_backend(
+        id: ClientId,
+        actor: Box<dyn Actor>,
+        interlocks: Interlocks,
+        client: C,
+    ) -> Self {
+        ActorClient {
+            signal_subject: Subject(format!("actor.{}.set_signal", id)),
+            actor,
+            interlocks,
+            current_signal: None,
+            watchdog: None,
+            safe_signal: SIGNAL_LOWER_BOUND,
+            accounting: Accounting::new(id.clone(), None, TimeStamp::now()),
+            lease: None,
+            lease_sub: None,
+            id,
+            client,
+        }
+    }
+
+    /// Take signals granted by the power manager, instead of the requested ones.
+    pub fn with_power_budget(mut self) -> Self {
+        self.signal_subject = PowerPubMsg::grant_subject(&self.id);
+        self
+    }
+
+    /// Signal applied at shutdown and when the watchdog trips.
+    /// The actor is expected to be started with it applied, as by `ActorConfig::get_actor`.
+    pub fn with_safe_signal(mut self, safe_signal: f32) -> Self {
+        self.safe_signal = safe_signal;
+        self.accounting.set_initial_signal(safe_signal);
+        self
+    }
+
+    /// Power at full signal in W, for estimating the energy use.
+    pub fn with_rated_power(mut self, rated_power: f32) -> Self {
+        self.accounting.set_rated_power(rated_power);
+        self
+    }
+
+    /// Apply the safe signal if no new signal arrives within `timeout`.
+    pub fn with_watchdog(mut self, timeout: Duration) -> Self {
+        self.watchdog = Some(Watchdog::new(timeout, Instant::now()));
+        self
+    }
+
+    /// Only accept signals from the holder of the lease on the actor, once it is leased.
+    /// The lease is subscribed to right away, so that the first one is not missed.
+    pub fn with_lease(mut self) -> Result<Self, PubSubError> {
+        self.lease_sub = Some(self.subscribe(&Lease::subject(&self.id))?);
+        Ok(self)
     }
 
     fn gen_signal_subject(&self) -> Subject {
         Subject(format!("actor.{}.current_signal", self.id))
     }
+
+    /// Request subject for the runtime and energy stats of the current session.
+    fn gen_stats_subject(&self) -> Subject {
+        Subject(format!("actor.{}.stats", self.id))
+    }
+
+    fn reply_stats(&mut self, msg: &Message) -> Result<(), PubSubError> {
+        let stats = self.accounting.stats(TimeStamp::now());
+        let reply = serde_json::to_string(&stats).expect("Pub sub serialization error");
+        msg.respond(reply).map_err(|err| PubSubError::Reply {
+            msg: msg.to_string(),
+            err: err.to_string(),
+        })
+    }
+
+    fn check_lease(&self, msg: &SignalMsg) -> Result<(), PubSubError> {
+        match &self.lease {
+            Some(lease) if !lease.allows(msg.source.as_ref(), TimeStamp::now()) => {
+                Err(PubSubError::Client(format!(
+                    "Signal {} from '{}' rejected, the actor is leased to '{}'",
+                    msg.signal,
+                    msg.source.as_ref().map_or("", |source| source.as_ref()),
+                    lease
+                        .holder(TimeStamp::now())
+                        .map_or("", |holder| holder.as_ref())
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply and publish a signal, or switch off if the signal violates an interlock.
+    /// A `forced` signal, like switching off, is applied regardless of the signal range.
+    fn apply_signal(
+        &mut self,
+        msg: SignalMsg,
+        running: bool,
+        forced: bool,
+    ) -> Result<(), PubSubError> {
+        let violation = self
+            .interlocks
+            .apply(self.actor.as_mut(), msg.signal, running, forced)?;
+        let msg = match violation {
+            Some(violation) => {
+                self.publish_violation(&msg, violation)?;
+                SignalMsg {
+                    signal: SIGNAL_LOWER_BOUND,
+                    ..msg
+                }
+            }
+            None => msg,
+        };
+        let signal = self.actor.effective_signal().unwrap_or(msg.signal);
+        let msg = SignalMsg {
+            signal,
+            moving: self.actor.is_moving(),
+            unknown: self.actor.is_unknown(),
+            ..msg
+        };
+        self.accounting.update_signal(msg.signal, TimeStamp::now());
+        self.current_signal = Some(msg.clone());
+        self.publish(
+            &self.gen_signal_subject(),
+            &ActorPubMsg::CurrentSignal(msg).into(),
+        )
+    }
+
+    fn publish_violation(&self, msg: &SignalMsg, violation: String) -> Result<(), PubSubError> {
+        let msg = format!("Signal {} overridden, {}", msg.signal, violation);
+        error(self, msg.clone(), &format!("actor.{}", self.id));
+        let violation = ActorPubMsg::Interlock {
+            id: self.id.clone(),
+            timestamp: TimeStamp::now(),
+            msg,
+        };
+        self.publish(&violation.subject(), &violation.into())
+    }
+
+    /// Report a failure of the actor itself, e.g. a valve stuck on its way.
+    fn publish_fault(&self, msg: String) -> Result<(), PubSubError> {
+        error(self, msg.clone(), &format!("actor.{}", self.id));
+        let fault = ActorPubMsg::Fault {
+            id: self.id.clone(),
+            timestamp: TimeStamp::now(),
+            msg,
+        };
+        self.publish(&fault.subject(), &fault.into())
+    }
+
+    fn check_watchdog(&mut self) -> Result<(), PubSubError> {
+        let timeout = match self.watchdog.as_mut() {
+            Some(watchdog) => {
+                if !watchdog.trip(Instant::now()) {
+                    return Ok(());
+                }
+                watchdog.timeout()
+            }
+            None => return Ok(()),
+        };
+        let msg = format!(
+            "No signal in {} ms, applying safe signal {}",
+            timeout.as_millis(),
+            self.safe_signal
+        );
+        error(self, msg.clone(), &format!("actor.{}", self.id));
+        let event = ActorPubMsg::Watchdog {
+            id: self.id.clone(),
+            timestamp: TimeStamp::now(),
+            msg,
+        };
+        // The safe signal comes first, since the watchdog only trips once. A failed report
+        // must not keep the actor at its last signal.
+        let safe = SignalMsg {
+            id: self.id.clone(),
+            timestamp: TimeStamp::now(),
+            signal: self.safe_signal,
+            moving: false,
+            unknown: false,
+            source: None,
+        };
+        let applied = self.apply_signal(safe, true, true);
+        if let Err(err) = self.publish(&event.subject(), &event.into()) {
+            error(
+                self,
+                format!("Could not report the watchdog trip: {}", err),
+                &format!("actor.{}", self.id),
+            );
+        }
+        applied
+    }
+
+    fn feed_watchdog(&mut self) {
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            if watchdog.feed(Instant::now()) {
+                info(
+                    self,
+                    String::from("New signal, watchdog re-armed"),
+                    &format!("actor.{}", self.id),
+                );
+            }
+        }
+    }
+
+    /// Publish the current signal again when the actor starts or stops moving,
+    /// or loses track of its signal, with the signal it ended up at.
+    fn publish_motion(&mut self) -> Result<(), PubSubError> {
+        let moving = self.actor.is_moving();
+        let unknown = self.actor.is_unknown();
+        match &self.current_signal {
+            Some(current) if current.moving != moving || current.unknown != unknown => {
+                let msg = SignalMsg {
+                    timestamp: TimeStamp::now(),
+                    signal: self.actor.effective_signal().unwrap_or(current.signal),
+                    moving,
+                    unknown,
+                    ..current.clone()
+                };
+                self.accounting.update_signal(msg.signal, msg.timestamp);
+                self.current_signal = Some(msg.clone());
+                self.publish(
+                    &self.gen_signal_subject(),
+                    &ActorPubMsg::CurrentSignal(msg).into(),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn update_interlocks(&mut self, msg: Message) -> Result<(), PubSubError> {
+        if msg.subject.starts_with("sensor.") {
+            self.interlocks
+                .update_measurement(SensorMsg::try_from(msg)?);
+        } else {
+            self.interlocks.update_signal(decode_nats_data(&msg.data)?);
+        }
+        Ok(())
+    }
+
+    /// Switch off if an interlock no longer holds for the applied signal.
+    fn enforce_interlocks(&mut self) -> Result<(), PubSubError> {
+        match &self.current_signal {
+            Some(current) if self.interlocks.violation(current.signal, true).is_some() => {
+                let msg = SignalMsg {
+                    timestamp: TimeStamp::now(),
+                    ..current.clone()
+                };
+                self.apply_signal(msg, true, false)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<C: PubSubBackend> Drop for ActorClient<C> {
+    fn drop(&mut self) {
+        if let Err(err) = self.actor.force_signal(self.safe_signal) {
+            error(
+                self,
+                format!("Could not apply safe signal: {}", err),
+                &format!("actor.{}", self.id),
+            );
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,6 +316,15 @@ pub struct SignalMsg {
     pub(crate) id: ClientId,
     pub(crate) timestamp: TimeStamp,
     pub(crate) signal: f32,
+    /// Set on `current_signal` while the actor is still on its way to the signal.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) moving: bool,
+    /// Set on `current_signal` when the actor lost track of its signal, e.g. after a fault.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) unknown: bool,
+    /// Client requesting the signal, checked against the lease of the actor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<ClientId>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -41,6 +335,12 @@ pub enum ActorSubMsg {
     // Stop,
 }
 
+impl Into<PubSubMsg> for ActorSubMsg {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
+
 impl TryFrom<Message> for ActorSubMsg {
     type Error = PubSubError;
     fn try_from(msg: Message) -> Result<Self, Self::Error> {
@@ -51,6 +351,37 @@ impl TryFrom<Message> for ActorSubMsg {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ActorPubMsg {
     CurrentSignal(SignalMsg),
+    #[serde(rename = "interlock")]
+    Interlock {
+        id: ClientId,
+        timestamp: TimeStamp,
+        msg: String,
+    },
+    #[serde(rename = "watchdog")]
+    Watchdog {
+        id: ClientId,
+        timestamp: TimeStamp,
+        msg: String,
+    },
+    #[serde(rename = "fault")]
+    Fault {
+        id: ClientId,
+        timestamp: TimeStamp,
+        msg: String,
+    },
+}
+
+impl ActorPubMsg {
+    pub fn subject(&self) -> Subject {
+        match self {
+            ActorPubMsg::CurrentSignal(signal_msg) => {
+                Subject(format!("actor.{}.current_signal", signal_msg.id))
+            }
+            ActorPubMsg::Interlock { id, .. } => Subject(format!("actor.{}.interlock", id)),
+            ActorPubMsg::Watchdog { id, .. } => Subject(format!("actor.{}.watchdog", id)),
+            ActorPubMsg::Fault { id, .. } => Subject(format!("actor.{}.fault", id)),
+        }
+    }
 }
 
 impl Into<PubSubMsg> for ActorPubMsg {
@@ -59,39 +390,97 @@ impl Into<PubSubMsg> for ActorPubMsg {
             ActorPubMsg::CurrentSignal(signal_msg) => {
                 PubSubMsg(serde_json::to_string(&signal_msg).expect("Pub sub serialization error"))
             }
+            ActorPubMsg::Interlock { .. }
+            | ActorPubMsg::Watchdog { .. }
+            | ActorPubMsg::Fault { .. } => {
+                PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+            }
         }
     }
 }
 
-impl PubSubClient for ActorClient {
+impl<C: PubSubBackend> PubSubClient for ActorClient<C> {
     fn client_loop(mut self) -> Result<(), PubSubError> {
         info(
             &self,
             format!("Starting actor with id '{}'", self.id),
             &format!("actor.{}", self.id),
         );
-        let sub = match self.subscribe(&Subject(format!("actor.{}.set_signal", self.id))) {
+        let sub = match self.subscribe(&self.signal_subject) {
             Ok(sub) => sub,
             Err(err) => {
                 error(&self, err.to_string(), &format!("actor.{}", self.id));
                 return Err(err);
             }
         };
-        // let mut state = ClientState::Active;
-        loop {
-            if let Some(contr_message) = sub.next() {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let interlocks = self
+            .interlocks
+            .subjects()
+            .iter()
+            .map(|subject| self.subscribe(subject))
+            .collect::<Result<Vec<_>, PubSubError>>()?;
+        let stats = self.subscribe(&self.gen_stats_subject())?;
+        let new_session = self.subscribe(&SupervisorSubMsg::NewSession.subject())?;
+        let lease = self.lease_sub.take();
+        let mut state = ClientState::Active;
+        let period = self.actor.tick_period().unwrap_or(POLL_PERIOD);
+        while state == ClientState::Active {
+            if let Some(msg) = kill_cmd.try_next() {
+                info(
+                    &self,
+                    String::from("Stopping actor"),
+                    &format!("actor.{}", self.id),
+                );
+                // The actor is dropped when the loop returns, which releases the hardware.
+                let report = serde_json::to_string(&self.current_signal)
+                    .expect("Pub sub serialization error");
+                msg.respond(report).map_err(|err| PubSubError::Reply {
+                    msg: msg.to_string(),
+                    err: err.to_string(),
+                })?;
+                state = ClientState::Inactive;
+                continue;
+            }
+            if new_session.try_next().is_some() {
+                self.accounting.reset(TimeStamp::now());
+                info(
+                    &self,
+                    String::from("New session, stats reset"),
+                    &format!("actor.{}", self.id),
+                );
+            }
+            if let Some(msg) = stats.try_next() {
+                if let Err(err) = self.reply_stats(&msg) {
+                    error(&self, err.to_string(), &format!("actor.{}", self.id));
+                }
+            }
+            for msg in lease.iter().flat_map(|sub| sub.try_iter()) {
+                match decode_nats_data(&msg.data) {
+                    Ok(lease) => self.lease = Some(lease),
+                    Err(err) => error(&self, err.to_string(), &format!("actor.{}", self.id)),
+                }
+            }
+            for msg in interlocks.iter().flat_map(|sub| sub.try_iter()) {
+                if let Err(err) = self.update_interlocks(msg) {
+                    error(&self, err.to_string(), &format!("actor.{}", self.id));
+                }
+            }
+            if let Err(err) = self.enforce_interlocks() {
+                error(&self, err.to_string(), &format!("actor.{}", self.id));
+            }
+            if let Ok(contr_message) = sub.next_timeout(period) {
                 let res: Result<(), PubSubError> = match ActorSubMsg::try_from(contr_message) {
                     Ok(msg) => match msg {
-                        ActorSubMsg::SetSignal(msg) => self
-                            .actor
-                            .set_signal(msg.signal)
-                            .map_err(|err| err.into())
-                            .and_then(|()| {
-                                self.publish(
-                                    &self.gen_signal_subject(),
-                                    &ActorPubMsg::CurrentSignal(msg).into(),
-                                )
-                            }),
+                        ActorSubMsg::SetSignal(msg) => self.check_lease(&msg).and_then(|_| {
+                            self.feed_watchdog();
+                            self.apply_signal(msg, false, false)
+                        }),
                     },
                     Err(err) => Err(err),
                 };
@@ -99,9 +488,19 @@ impl PubSubClient for ActorClient {
                     error(&self, err.to_string(), &format!("actor.{}", self.id));
                 }
             }
+            if let Err(err) = self.check_watchdog() {
+                error(&self, err.to_string(), &format!("actor.{}", self.id));
+            }
+            if let Err(err) = self.actor.tick() {
+                if let Err(err) = self.publish_fault(err.to_string()) {
+                    error(&self, err.to_string(), &format!("actor.{}", self.id));
+                }
+            }
+            if let Err(err) = self.publish_motion() {
+                error(&self, err.to_string(), &format!("actor.{}", self.id));
+            }
         }
-        // TODO: Exit gracefully
-        // Ok(())
+        Ok(())
     }
 
     fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
@@ -112,3 +511,48 @@ impl PubSubClient for ActorClient {
         self.client.publish(subject, msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::dummy::DummyActor;
+
+    /// Backend without a server, where every publish fails.
+    struct Offline;
+
+    impl PubSubBackend for Offline {
+        fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+            Err(PubSubError::Subscription(subject.0.clone()))
+        }
+
+        fn publish(&self, subject: &Subject, _msg: &PubSubMsg) -> Result<(), PubSubError> {
+            Err(PubSubError::Publish(subject.0.clone()))
+        }
+    }
+
+    #[test]
+    fn test_watchdog_without_server() {
+        let id = ClientId("heater".into());
+        let interlocks = Interlocks::new(&id, &[]);
+        let mut client = ActorClient::with_backend(
+            id.clone(),
+            Box::new(DummyActor::default()),
+            interlocks,
+            Offline,
+        )
+        .with_safe_signal(0.0)
+        .with_watchdog(Duration::from_millis(0));
+        let on = SignalMsg {
+            id,
+            timestamp: TimeStamp::now(),
+            signal: 1.0,
+            moving: false,
+            unknown: false,
+            source: None,
+        };
+        assert!(client.apply_signal(on, false, false).is_err());
+        assert_eq!(client.actor.effective_signal(), Some(1.0));
+        assert!(client.check_watchdog().is_err());
+        assert_eq!(client.actor.effective_signal(), Some(0.0));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::actor::{Actor, ActorError};
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
 use embedded_hal::digital::OutputPin;
 
 pub struct SimpleGpioActor<T: OutputPin + Send> {
@@ -17,13 +17,13 @@ impl<T: OutputPin + Send> SimpleGpioActor<T> {
 
 impl<T: OutputPin + Send> Actor for SimpleGpioActor<T> {
     fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
-        if signal >= 0.0 {
+        if (SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&signal) {
             Ok(())
         } else {
             Err(ActorError::InvalidSignal {
                 signal,
-                lower_bound: 0.0,
-                upper_bound: 1.0,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
             })
         }
     }
@@ -37,7 +37,7 @@ impl<T: OutputPin + Send> Actor for SimpleGpioActor<T> {
         } else {
             self.handle
                 .try_set_low()
-                .map_err(|_err| ActorError::Generic(String::from("GPIO error when setting high")))
+                .map_err(|_err| ActorError::Generic(String::from("GPIO error when setting low")))
         }
     }
 }
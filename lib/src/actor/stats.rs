@@ -0,0 +1,132 @@
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+
+const SECS_PER_HOUR: f64 = 3600.0;
+const W_PER_KW: f64 = 1000.0;
+
+/// Runtime and energy use of an actor since the start of the session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActorStats {
+    pub(crate) id: ClientId,
+    /// Start of the session.
+    pub(crate) since: TimeStamp,
+    /// Time with a signal above zero, in s.
+    pub(crate) on_time: f64,
+    /// Number of times the actor was switched on.
+    pub(crate) switch_count: u64,
+    /// Estimated energy use in kWh, from the rated power times the applied signal.
+    /// `None` for actors without a rated power.
+    pub(crate) energy: Option<f64>,
+}
+
+/// Integrates the applied signal of an actor over time.
+pub struct Accounting {
+    id: ClientId,
+    rated_power: Option<f32>,
+    signal: f32,
+    last_update: TimeStamp,
+    since: TimeStamp,
+    on_time: f64,
+    switch_count: u64,
+    /// Energy in Ws, to avoid rounding small increments away.
+    energy: f64,
+}
+
+impl Accounting {
+    pub fn new(id: ClientId, rated_power: Option<f32>, now: TimeStamp) -> Self {
+        Accounting {
+            id,
+            rated_power,
+            signal: 0.0,
+            last_update: now,
+            since: now,
+            on_time: 0.0,
+            switch_count: 0,
+            energy: 0.0,
+        }
+    }
+
+    pub fn set_rated_power(&mut self, rated_power: f32) {
+        self.rated_power = Some(rated_power);
+    }
+
+    /// Signal applied before the accounting started, e.g. the safe signal at startup.
+    /// Unlike `update_signal`, this does not count as switching the actor on.
+    pub fn set_initial_signal(&mut self, signal: f32) {
+        self.signal = signal;
+    }
+
+    pub fn update_signal(&mut self, signal: f32, now: TimeStamp) {
+        self.accumulate(now);
+        if signal > 0.0 && self.signal <= 0.0 {
+            self.switch_count += 1;
+        }
+        self.signal = signal;
+    }
+
+    pub fn stats(&mut self, now: TimeStamp) -> ActorStats {
+        self.accumulate(now);
+        ActorStats {
+            id: self.id.clone(),
+            since: self.since,
+            on_time: self.on_time,
+            switch_count: self.switch_count,
+            energy: self
+                .rated_power
+                .map(|_| self.energy / SECS_PER_HOUR / W_PER_KW),
+        }
+    }
+
+    /// Start a new session, with the current signal still applied.
+    pub fn reset(&mut self, now: TimeStamp) {
+        self.last_update = now;
+        self.since = now;
+        self.on_time = 0.0;
+        self.switch_count = 0;
+        self.energy = 0.0;
+    }
+
+    fn accumulate(&mut self, now: TimeStamp) {
+        let dt = f64::from(now.secs_since(self.last_update).max(0.0));
+        if self.signal > 0.0 {
+            self.on_time += dt;
+        }
+        self.energy += f64::from(self.signal * self.rated_power.unwrap_or(0.0)) * dt;
+        self.last_update = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_accounting() {
+        let mut accounting = Accounting::new(ClientId("boil".into()), Some(3600.0), TimeStamp(0));
+        accounting.update_signal(1.0, TimeStamp(10_000));
+        accounting.update_signal(0.5, TimeStamp(1_010_000));
+        accounting.update_signal(0.0, TimeStamp(2_010_000));
+        accounting.update_signal(1.0, TimeStamp(3_000_000));
+        let stats = accounting.stats(TimeStamp(3_000_000));
+        assert_approx_eq!(stats.on_time, 2000.0);
+        assert_eq!(stats.switch_count, 2);
+        // 3.6 kW for 1000 s and 1.8 kW for 1000 s.
+        assert_approx_eq!(stats.energy.unwrap(), 1.5);
+
+        accounting.reset(TimeStamp(4_000_000));
+        let stats = accounting.stats(TimeStamp(4_360_000));
+        assert_eq!(stats.since, TimeStamp(4_000_000));
+        assert_approx_eq!(stats.on_time, 360.0);
+        assert_eq!(stats.switch_count, 0);
+        assert_approx_eq!(stats.energy.unwrap(), 0.36);
+
+        let mut accounting = Accounting::new(ClientId("pump".into()), None, TimeStamp(0));
+        accounting.set_initial_signal(1.0);
+        let stats = accounting.stats(TimeStamp(1000));
+        assert!(stats.energy.is_none());
+        assert_approx_eq!(stats.on_time, 1.0);
+        assert_eq!(stats.switch_count, 0);
+    }
+}
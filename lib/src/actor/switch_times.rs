@@ -0,0 +1,171 @@
+use crate::actor::{Actor, ActorError};
+use crate::time::{Clock, TimeStamp};
+use std::time::Duration;
+
+/// Resolution of the min. on and off times.
+const TICK_PERIOD: Duration = Duration::from_millis(500);
+
+/// Actor kept on and off for min. times, e.g. to not short-cycle a compressor.
+///
+/// A switch which comes too early is held back until the time is up, and dropped if the
+/// signal returns before that. Forced signals are applied right away, since they are
+/// needed for safety, but still restart the timing.
+pub struct MinSwitchTimes {
+    actor: Box<dyn Actor>,
+    min_on: f32,
+    min_off: f32,
+    signal: f32,
+    /// Time of the last switch, `None` before the first signal.
+    switched: Option<TimeStamp>,
+    /// Signal waiting for the min. on or off time to pass.
+    pending: Option<f32>,
+    clock: Box<dyn Clock>,
+}
+
+impl MinSwitchTimes {
+    pub fn new(
+        actor: Box<dyn Actor>,
+        min_on_ms: u64,
+        min_off_ms: u64,
+        clock: Box<dyn Clock>,
+    ) -> MinSwitchTimes {
+        MinSwitchTimes {
+            actor,
+            min_on: min_on_ms as f32 / 1000.0,
+            min_off: min_off_ms as f32 / 1000.0,
+            signal: 0.0,
+            switched: None,
+            pending: None,
+            clock,
+        }
+    }
+
+    fn is_on(&self) -> bool {
+        self.signal > 0.0
+    }
+
+    fn may_switch(&self, now: TimeStamp) -> bool {
+        let min_time = if self.is_on() {
+            self.min_on
+        } else {
+            self.min_off
+        };
+        self.switched
+            .map_or(true, |switched| now.secs_since(switched) >= min_time)
+    }
+
+    fn apply(&mut self, signal: f32, forced: bool, now: TimeStamp) -> Result<(), ActorError> {
+        if forced {
+            self.actor.force_signal(signal)?;
+        } else {
+            self.actor.set_signal(signal)?;
+        }
+        if self.switched.is_none() || (signal > 0.0) != self.is_on() {
+            self.switched = Some(now);
+        }
+        self.signal = signal;
+        self.pending = None;
+        Ok(())
+    }
+}
+
+impl Actor for MinSwitchTimes {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        self.actor.validate_signal(signal)
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.validate_signal(signal)?;
+        let now = self.clock.now();
+        if (signal > 0.0) == self.is_on() || self.may_switch(now) {
+            self.apply(signal, false, now)
+        } else {
+            self.pending = Some(signal);
+            Ok(())
+        }
+    }
+
+    fn force_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        let now = self.clock.now();
+        self.apply(signal, true, now)
+    }
+
+    fn effective_signal(&self) -> Option<f32> {
+        Some(self.actor.effective_signal().unwrap_or(self.signal))
+    }
+
+    fn is_moving(&self) -> bool {
+        self.pending.is_some() || self.actor.is_moving()
+    }
+
+    fn is_unknown(&self) -> bool {
+        self.actor.is_unknown()
+    }
+
+    fn tick_period(&self) -> Option<Duration> {
+        Some(
+            self.actor
+                .tick_period()
+                .map_or(TICK_PERIOD, |period| period.min(TICK_PERIOD)),
+        )
+    }
+
+    fn tick(&mut self) -> Result<(), ActorError> {
+        self.actor.tick()?;
+        let now = self.clock.now();
+        match self.pending {
+            Some(signal) if self.may_switch(now) => self.apply(signal, false, now),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actor::dummy::DummyActor;
+    use crate::time::ManualClock;
+
+    #[test]
+    fn test_min_switch_times() {
+        let clock = ManualClock::new(TimeStamp(0));
+        let mut actor = MinSwitchTimes::new(
+            Box::new(DummyActor::default()),
+            60_000,
+            300_000,
+            Box::new(clock.clone()),
+        );
+        actor.force_signal(0.0).unwrap();
+        actor.set_signal(1.0).unwrap();
+        assert!(actor.is_moving());
+        assert_eq!(actor.effective_signal(), Some(0.0));
+        clock.advance(300.0);
+        actor.tick().unwrap();
+        assert!(!actor.is_moving());
+        assert_eq!(actor.effective_signal(), Some(1.0));
+
+        // Kept on for the min. on-time, but modulating is fine.
+        clock.advance(30.0);
+        actor.set_signal(0.5).unwrap();
+        assert_eq!(actor.effective_signal(), Some(0.5));
+        actor.set_signal(0.0).unwrap();
+        assert_eq!(actor.effective_signal(), Some(0.5));
+        clock.advance(30.0);
+        actor.tick().unwrap();
+        assert_eq!(actor.effective_signal(), Some(0.0));
+
+        // A pending switch is dropped when the signal returns.
+        actor.set_signal(1.0).unwrap();
+        actor.set_signal(0.0).unwrap();
+        clock.advance(300.0);
+        actor.tick().unwrap();
+        assert_eq!(actor.effective_signal(), Some(0.0));
+
+        // Forced signals are applied right away.
+        actor.set_signal(1.0).unwrap();
+        actor.force_signal(0.0).unwrap();
+        assert!(!actor.is_moving());
+        actor.set_signal(1.0).unwrap();
+        assert_eq!(actor.effective_signal(), Some(0.0));
+    }
+}
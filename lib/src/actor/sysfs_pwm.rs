@@ -0,0 +1,151 @@
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Max. time for the kernel to create the channel directory after an export.
+const EXPORT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Hardware PWM through the Linux sysfs interface, e.g. for pump speed or dimmable elements.
+///
+/// The duty cycle is the signal times the period. The channel is exported when the actor is
+/// created and released when it is dropped.
+pub struct SysfsPwmActor {
+    pub id: String,
+    chip_dir: PathBuf,
+    channel: u32,
+    period_ns: u64,
+}
+
+impl SysfsPwmActor {
+    pub fn try_new(
+        id: &str,
+        root: &Path,
+        chip: u32,
+        channel: u32,
+        period_ns: u64,
+        active_low: bool,
+    ) -> Result<SysfsPwmActor, ActorError> {
+        if period_ns == 0 {
+            return Err(ActorError::Generic(String::from(
+                "PWM period must be positive",
+            )));
+        }
+        let actor = SysfsPwmActor {
+            id: id.into(),
+            chip_dir: root.join(format!("pwmchip{}", chip)),
+            channel,
+            period_ns,
+        };
+        actor.export()?;
+        // The duty cycle may never exceed the period, so it is reset before the period is set.
+        actor.write("duty_cycle", 0)?;
+        actor.write("period", period_ns)?;
+        // The polarity can only be changed while disabled.
+        actor.write("enable", 0)?;
+        let polarity = if active_low { "inversed" } else { "normal" };
+        write_value(&actor.channel_dir().join("polarity"), polarity)?;
+        actor.write("enable", 1)?;
+        Ok(actor)
+    }
+
+    fn channel_dir(&self) -> PathBuf {
+        self.chip_dir.join(format!("pwm{}", self.channel))
+    }
+
+    fn export(&self) -> Result<(), ActorError> {
+        if self.channel_dir().exists() {
+            return Ok(());
+        }
+        write_value(&self.chip_dir.join("export"), self.channel)?;
+        let start = Instant::now();
+        while !self.channel_dir().exists() {
+            if start.elapsed() > EXPORT_TIMEOUT {
+                return Err(ActorError::Generic(format!(
+                    "PWM channel '{}' of '{}' not created after export",
+                    self.channel_dir().display(),
+                    self.id
+                )));
+            }
+            sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    fn write(&self, attribute: &str, value: u64) -> Result<(), ActorError> {
+        write_value(&self.channel_dir().join(attribute), value)
+    }
+}
+
+fn write_value(path: &Path, value: impl ToString) -> Result<(), ActorError> {
+    fs::write(path, value.to_string()).map_err(|err| {
+        ActorError::Generic(format!("Could not write '{}': {}", path.display(), err))
+    })
+}
+
+impl Actor for SysfsPwmActor {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        if (SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&signal) {
+            Ok(())
+        } else {
+            Err(ActorError::InvalidSignal {
+                signal,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
+            })
+        }
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.validate_signal(signal)?;
+        let duty_cycle = (f64::from(signal) * self.period_ns as f64).round() as u64;
+        self.write("duty_cycle", duty_cycle.min(self.period_ns))
+    }
+}
+
+impl Drop for SysfsPwmActor {
+    fn drop(&mut self) {
+        // Best effort, there is no one left to report errors to.
+        let _ = self.write("duty_cycle", 0);
+        let _ = self.write("enable", 0);
+        let _ = write_value(&self.chip_dir.join("unexport"), self.channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_fake_sysfs() {
+        let root = std::env::temp_dir().join(format!("bryggio-pwm-{}", std::process::id()));
+        let channel_dir = root.join("pwmchip0").join("pwm1");
+        fs::create_dir_all(&channel_dir).unwrap();
+
+        let mut actor = SysfsPwmActor::try_new("pump", &root, 0, 1, 40_000, true).unwrap();
+        assert_eq!(read(&channel_dir.join("period")), "40000");
+        assert_eq!(read(&channel_dir.join("polarity")), "inversed");
+        assert_eq!(read(&channel_dir.join("enable")), "1");
+        actor.set_signal(0.25).unwrap();
+        assert_eq!(read(&channel_dir.join("duty_cycle")), "10000");
+        assert!(actor.set_signal(1.5).is_err());
+        assert_eq!(read(&channel_dir.join("duty_cycle")), "10000");
+
+        drop(actor);
+        assert_eq!(read(&channel_dir.join("duty_cycle")), "0");
+        assert_eq!(read(&channel_dir.join("enable")), "0");
+        assert_eq!(read(&root.join("pwmchip0").join("unexport")), "1");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_missing_chip() {
+        let root = std::env::temp_dir().join(format!("bryggio-no-pwm-{}", std::process::id()));
+        assert!(SysfsPwmActor::try_new("pump", &root, 0, 0, 40_000, false).is_err());
+    }
+}
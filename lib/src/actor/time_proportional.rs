@@ -0,0 +1,232 @@
+use crate::actor::{Actor, ActorError, SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
+use crate::time::{Clock, TimeStamp};
+use embedded_hal::digital::OutputPin;
+use std::time::Duration;
+
+/// Resolution of the on/off pattern.
+const TICK_PERIOD: Duration = Duration::from_millis(100);
+
+/// On/off pattern over a fixed window, with the on-time proportional to the duty.
+///
+/// Pulses shorter than `min_pulse` are avoided, by rounding the duty to fully off or on.
+/// A new duty takes effect at the start of the next window, so that a window is never cut
+/// short, except a zero duty which turns the output off at once.
+pub struct DutyCycle {
+    window: f32,
+    min_pulse: f32,
+    duty: f32,
+    pending_duty: f32,
+    window_start: Option<TimeStamp>,
+}
+
+impl DutyCycle {
+    pub fn try_new(window: f32, min_pulse: f32) -> Result<DutyCycle, ActorError> {
+        if window <= 0.0 {
+            return Err(ActorError::Generic(format!(
+                "Window must be positive ({} !> 0.0)",
+                window
+            )));
+        }
+        if min_pulse < 0.0 || 2.0 * min_pulse > window {
+            return Err(ActorError::Generic(format!(
+                "Min. pulse must be in [0.0, {}], got {}",
+                window / 2.0,
+                min_pulse
+            )));
+        }
+        Ok(DutyCycle {
+            window,
+            min_pulse,
+            duty: 0.0,
+            pending_duty: 0.0,
+            window_start: None,
+        })
+    }
+
+    /// Set a new duty and return the effective duty, after rounding to the min. pulse length.
+    pub fn set_duty(&mut self, duty: f32) -> f32 {
+        let on_time = duty * self.window;
+        self.pending_duty = if on_time < self.min_pulse {
+            0.0
+        } else if self.window - on_time < self.min_pulse {
+            1.0
+        } else {
+            duty
+        };
+        if self.pending_duty == 0.0 {
+            self.duty = 0.0;
+        }
+        self.pending_duty
+    }
+
+    /// Whether the output should be on at `now`.
+    pub fn output(&mut self, now: TimeStamp) -> bool {
+        let window_start = match self.window_start {
+            Some(window_start) => window_start,
+            None => {
+                self.duty = self.pending_duty;
+                *self.window_start.insert(now)
+            }
+        };
+        let delta = now.secs_since(window_start);
+        if delta >= self.window {
+            let num_windows = (delta / self.window).floor();
+            self.window_start = Some(window_start.add_secs(num_windows * self.window));
+            self.duty = self.pending_duty;
+        }
+        calculate_cycle_ratio(delta, self.window) < self.duty
+    }
+}
+
+fn calculate_cycle_ratio(delta: f32, cycle_length: f32) -> f32 {
+    (delta % cycle_length) / cycle_length
+}
+
+/// Actor switching a GPIO pin, e.g. an SSR, in a time-proportioned pattern.
+pub struct TimeProportionalActor<T: OutputPin + Send> {
+    pub id: String,
+    handle: T,
+    duty_cycle: DutyCycle,
+    effective_signal: f32,
+    high: bool,
+    clock: Box<dyn Clock>,
+}
+
+impl<T: OutputPin + Send> TimeProportionalActor<T> {
+    pub fn try_new(
+        id: &str,
+        handle: T,
+        window_ms: u64,
+        min_pulse_ms: u64,
+        clock: Box<dyn Clock>,
+    ) -> Result<TimeProportionalActor<T>, ActorError> {
+        let mut actor = TimeProportionalActor {
+            id: id.into(),
+            handle,
+            duty_cycle: DutyCycle::try_new(
+                window_ms as f32 / 1000.0,
+                min_pulse_ms as f32 / 1000.0,
+            )?,
+            effective_signal: SIGNAL_LOWER_BOUND,
+            high: true,
+            clock,
+        };
+        actor.set_output(false)?;
+        Ok(actor)
+    }
+
+    fn set_output(&mut self, high: bool) -> Result<(), ActorError> {
+        if high == self.high {
+            return Ok(());
+        }
+        let res = if high {
+            self.handle.try_set_high()
+        } else {
+            self.handle.try_set_low()
+        };
+        res.map_err(|_err| {
+            ActorError::Generic(format!("GPIO error when switching '{}'", self.id))
+        })?;
+        self.high = high;
+        Ok(())
+    }
+}
+
+impl<T: OutputPin + Send> Actor for TimeProportionalActor<T> {
+    fn validate_signal(&self, signal: f32) -> Result<(), ActorError> {
+        if (SIGNAL_LOWER_BOUND..=SIGNAL_UPPER_BOUND).contains(&signal) {
+            Ok(())
+        } else {
+            Err(ActorError::InvalidSignal {
+                signal,
+                lower_bound: SIGNAL_LOWER_BOUND,
+                upper_bound: SIGNAL_UPPER_BOUND,
+            })
+        }
+    }
+
+    fn set_signal(&mut self, signal: f32) -> Result<(), ActorError> {
+        self.validate_signal(signal)?;
+        self.effective_signal = self.duty_cycle.set_duty(signal);
+        self.tick()
+    }
+
+    fn effective_signal(&self) -> Option<f32> {
+        Some(self.effective_signal)
+    }
+
+    fn tick_period(&self) -> Option<Duration> {
+        Some(TICK_PERIOD)
+    }
+
+    fn tick(&mut self) -> Result<(), ActorError> {
+        let high = self.duty_cycle.output(self.clock.now());
+        self.set_output(high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{mock, GpioBackend};
+    use crate::time::ManualClock;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn pattern(duty_cycle: &mut DutyCycle, start: u128) -> Vec<bool> {
+        (0..10)
+            .map(|secs| duty_cycle.output(TimeStamp(start + secs * 1000)))
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut duty_cycle = DutyCycle::try_new(10.0, 1.0).unwrap();
+        assert_approx_eq!(duty_cycle.set_duty(0.3), 0.3);
+        assert_eq!(
+            pattern(&mut duty_cycle, 5000),
+            vec![true, true, true, false, false, false, false, false, false, false]
+        );
+        // Takes effect in the next window.
+        duty_cycle.set_duty(0.5);
+        assert!(!duty_cycle.output(TimeStamp(14500)));
+        assert_eq!(
+            pattern(&mut duty_cycle, 15000),
+            vec![true, true, true, true, true, false, false, false, false, false]
+        );
+        duty_cycle.set_duty(0.0);
+        assert!(!duty_cycle.output(TimeStamp(25000)));
+    }
+
+    #[test]
+    fn test_min_pulse() {
+        let mut duty_cycle = DutyCycle::try_new(10.0, 2.0).unwrap();
+        assert_approx_eq!(duty_cycle.set_duty(0.1), 0.0);
+        assert_approx_eq!(duty_cycle.set_duty(0.85), 1.0);
+        assert_approx_eq!(duty_cycle.set_duty(0.5), 0.5);
+        assert!(DutyCycle::try_new(10.0, 6.0).is_err());
+    }
+
+    #[test]
+    fn test_actor() {
+        let clock = ManualClock::new(TimeStamp(0));
+        let pin = GpioBackend::Mock.get_gpio_pin(60, "ssr", false).unwrap();
+        let mut actor =
+            TimeProportionalActor::try_new("ssr", pin, 10_000, 1000, Box::new(clock.clone()))
+                .unwrap();
+        assert_eq!(mock::pin_state(60), Some(false));
+        actor.set_signal(0.3).unwrap();
+        assert_eq!(mock::pin_state(60), Some(true));
+        clock.advance(2.9);
+        actor.tick().unwrap();
+        assert_eq!(mock::pin_state(60), Some(true));
+        clock.advance(0.1);
+        actor.tick().unwrap();
+        assert_eq!(mock::pin_state(60), Some(false));
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        assert_approx_eq!(calculate_cycle_ratio(17.0, 10.0), 0.7);
+        assert_approx_eq!(calculate_cycle_ratio(27.0, 10.0), 0.7);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+/// Trips once when not fed within the timeout, and is re-armed by feeding it again.
+pub struct Watchdog {
+    timeout: Duration,
+    last_fed: Instant,
+    tripped: bool,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Watchdog {
+            timeout,
+            last_fed: now,
+            tripped: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Re-arm the watchdog, returns whether it had tripped.
+    pub fn feed(&mut self, now: Instant) -> bool {
+        self.last_fed = now;
+        std::mem::replace(&mut self.tripped, false)
+    }
+
+    /// True the first time the timeout has passed since the last feed.
+    pub fn trip(&mut self, now: Instant) -> bool {
+        if !self.tripped && now.saturating_duration_since(self.last_fed) >= self.timeout {
+            self.tripped = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut watchdog = Watchdog::new(Duration::from_secs(10), start);
+        assert!(!watchdog.trip(secs(9)));
+        assert!(watchdog.trip(secs(10)));
+        assert!(!watchdog.trip(secs(11)));
+
+        assert!(watchdog.feed(secs(12)));
+        assert!(!watchdog.feed(secs(13)));
+        assert!(!watchdog.trip(secs(22)));
+        assert!(watchdog.trip(secs(23)));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::actor::{SIGNAL_LOWER_BOUND, SIGNAL_UPPER_BOUND};
+use crate::control;
+use crate::control::hysteresis;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::f32;
+use std::f32::consts::PI;
+
+/// Relay feedback auto-tuning (Åström–Hägglund)
+///
+/// A hysteresis controller is used as the relay, which forces the process into a limit cycle.
+/// The amplitude and period of the cycle give the ultimate gain and period of the process,
+/// from which PID gains are suggested with the Ziegler–Nichols rules.
+/// When enough cycles are recorded, the relay is switched off.
+pub struct Controller {
+    relay: hysteresis::Controller,
+    /// Half-width of the relay hysteresis band.
+    hysteresis: f32,
+    cycles: usize,
+    /// Time of the first measurement.
+    start: Option<TimeStamp>,
+    cycle_start: Option<f32>,
+    cycle_min: f32,
+    cycle_max: f32,
+    transient_passed: bool,
+    periods: Vec<f32>,
+    amplitudes: Vec<f32>,
+    result: Option<TuningResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TuningResult {
+    pub ultimate_gain: f32,
+    pub ultimate_period: f32,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Controller {
+    pub fn try_new(
+        target: f32,
+        offset_on: f32,
+        offset_off: f32,
+        cycles: usize,
+    ) -> Result<Controller, control::ControllerError> {
+        if cycles == 0 {
+            return Err(control::ControllerError::ParamError(String::from(
+                "At least one cycle is required for auto-tuning",
+            )));
+        }
+        let relay = hysteresis::Controller::try_new(target, offset_on, offset_off)?;
+        Ok(Controller {
+            relay,
+            hysteresis: (offset_on - offset_off) / 2.0,
+            cycles,
+            start: None,
+            cycle_start: None,
+            cycle_min: f32::INFINITY,
+            cycle_max: f32::NEG_INFINITY,
+            transient_passed: false,
+            periods: Vec::new(),
+            amplitudes: Vec::new(),
+            result: None,
+        })
+    }
+
+    fn update(&mut self, measurement: f32, timestamp: TimeStamp) -> f32 {
+        if self.result.is_some() {
+            return self.relay.current_signal;
+        }
+        // Seconds since the start of the experiment.
+        let time = timestamp.secs_since(*self.start.get_or_insert(timestamp));
+        let previous_signal = self.relay.current_signal;
+        let signal =
+            control::Control::calculate_signal(&mut self.relay, Some(measurement), timestamp);
+        self.cycle_min = self.cycle_min.min(measurement);
+        self.cycle_max = self.cycle_max.max(measurement);
+
+        // A full cycle is completed every time the relay switches on.
+        if previous_signal <= 0.0 && signal > 0.0 {
+            if let Some(cycle_start) = self.cycle_start {
+                // The first cycle starts from an arbitrary state and is discarded.
+                if self.transient_passed {
+                    self.periods.push(time - cycle_start);
+                    self.amplitudes
+                        .push((self.cycle_max - self.cycle_min) / 2.0);
+                }
+                self.transient_passed = true;
+            }
+            self.cycle_start = Some(time);
+            self.cycle_min = measurement;
+            self.cycle_max = measurement;
+        }
+
+        if self.periods.len() >= self.cycles {
+            self.result = Some(tuning_result(
+                mean(&self.amplitudes),
+                mean(&self.periods),
+                (SIGNAL_UPPER_BOUND - SIGNAL_LOWER_BOUND) / 2.0,
+                self.hysteresis,
+            ));
+            self.relay.current_signal = SIGNAL_LOWER_BOUND;
+        }
+        self.relay.current_signal
+    }
+}
+
+impl control::Control for Controller {
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        match measurement {
+            Some(measurement) => self.update(measurement, timestamp),
+            None => self.relay.current_signal,
+        }
+    }
+
+    fn get_state(&self) -> control::State {
+        self.relay.get_state()
+    }
+
+    fn set_state(&mut self, new_state: control::State) {
+        self.relay.set_state(new_state);
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        self.relay.set_target(new_target);
+    }
+
+    fn get_target(&self) -> f32 {
+        self.relay.get_target()
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.relay.get_control_signal()
+    }
+
+    fn tuning_result(&self) -> Option<TuningResult> {
+        self.result.clone()
+    }
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+/// Ultimate gain from the describing function of a relay with hysteresis,
+/// followed by the classic Ziegler–Nichols PID rules.
+fn tuning_result(
+    amplitude: f32,
+    period: f32,
+    relay_amplitude: f32,
+    hysteresis: f32,
+) -> TuningResult {
+    let effective_amplitude = if amplitude > hysteresis {
+        (amplitude.powi(2) - hysteresis.powi(2)).sqrt()
+    } else {
+        amplitude
+    };
+    let ultimate_gain = 4.0 * relay_amplitude / (PI * effective_amplitude);
+    let kp = 0.6 * ultimate_gain;
+    TuningResult {
+        ultimate_gain,
+        ultimate_period: period,
+        kp,
+        ki: 2.0 * kp / period,
+        kd: kp * period / 8.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Control;
+    use crate::time::{Clock, ManualClock};
+    use assert_approx_eq::assert_approx_eq;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_constructor_no_cycles() {
+        let controller = Controller::try_new(50.0, 1.0, 0.5, 0);
+        assert!(controller.is_err())
+    }
+
+    #[test]
+    fn test_tuning_result_no_hysteresis() {
+        let result = tuning_result(1.0, 100.0, 0.5, 0.0);
+        assert_approx_eq!(result.ultimate_gain, 2.0 / PI);
+        assert_approx_eq!(result.kp, 0.6 * 2.0 / PI);
+        assert_approx_eq!(result.ki, 1.2 * 2.0 / PI / 100.0);
+        assert_approx_eq!(result.kd, 0.075 * 2.0 / PI * 100.0);
+    }
+
+    #[test]
+    fn test_relay_experiment() {
+        let mut controller = Controller::try_new(50.0, 1.0, 0.5, 3).unwrap();
+        // Integrating process with dead time, heating or cooling at 0.1 deg/s.
+        let mut temp = 40.0;
+        let mut delayed_signals: VecDeque<f32> = vec![0.0; 20].into_iter().collect();
+        let clock = ManualClock::new(TimeStamp(1_000_000));
+        while controller.tuning_result().is_none() && clock.now().0 < 11_000_000 {
+            let signal = controller.calculate_signal(Some(temp), clock.now());
+            delayed_signals.push_back(signal);
+            let applied = delayed_signals.pop_front().unwrap();
+            temp += if applied > 0.0 { 0.1 } else { -0.1 };
+            clock.advance(1.0);
+        }
+        let result = controller.tuning_result().unwrap();
+        // Symmetric process: dead time of 20 s on the way up and down.
+        assert!(result.ultimate_period > 85.0 && result.ultimate_period < 95.0);
+        assert!(result.kp > 0.0 && result.ki > 0.0 && result.kd > 0.0);
+        assert_approx_eq!(controller.get_control_signal(), 0.0);
+    }
+}
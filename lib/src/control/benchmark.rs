@@ -0,0 +1,312 @@
+//! Offline controller benchmarking
+//!
+//! Runs a controller built from a `ControllerConfig` against a plant model, or a recorded
+//! measurement file, without NATS, and computes step-response metrics.
+use crate::control::{Control, ControllerConfig, ControllerError};
+use crate::sensor::simulated_vessel::{VesselModel, VesselParams};
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BenchmarkConfig {
+    pub(crate) controller: ControllerConfig,
+    pub(crate) target: f32,
+    pub(crate) process: ProcessConfig,
+    /// Max. distance from the target, for the measurement to count as settled.
+    #[serde(default = "default_settling_band")]
+    pub(crate) settling_band: f32,
+}
+
+fn default_settling_band() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProcessConfig {
+    /// Closed loop against a simulated vessel.
+    #[serde(rename = "vessel")]
+    Vessel {
+        #[serde(flatten)]
+        params: VesselParams,
+        initial: f32,
+        /// Sample time in seconds.
+        sample_time: f32,
+        /// Duration in seconds.
+        duration: f32,
+    },
+    /// Open loop against recorded measurements, with one `time,measurement` pair per line.
+    #[serde(rename = "recorded")]
+    Recorded(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Time in seconds since the start.
+    pub time: f32,
+    pub measurement: f32,
+    pub signal: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Metrics {
+    /// Max. distance past the target, in the direction of the step.
+    pub overshoot: f32,
+    /// Time from 10 % to 90 % of the step, if reached.
+    pub rise_time: Option<f32>,
+    /// Time after which the measurement stays within the settling band, if it does.
+    pub settling_time: Option<f32>,
+    /// Integrated absolute error in °C s.
+    pub iae: f32,
+    /// Number of switches between heating, idle and cooling.
+    pub switch_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Benchmark {
+    pub metrics: Metrics,
+    pub samples: Vec<Sample>,
+}
+
+/// Run the benchmark described by `config`.
+pub fn run(config: &BenchmarkConfig) -> Result<Benchmark, BenchmarkError> {
+    let mut controller = config.controller.get_controller(config.target)?;
+    let samples = match &config.process {
+        ProcessConfig::Vessel {
+            params,
+            initial,
+            sample_time,
+            duration,
+        } => {
+            if *sample_time <= 0.0 {
+                return Err(BenchmarkError::Config(format!(
+                    "Sample time must be positive ({} !> 0.0)",
+                    sample_time
+                )));
+            }
+            let model = VesselModel::new(params.clone(), *initial);
+            run_vessel(controller.as_mut(), model, *sample_time, *duration)
+        }
+        ProcessConfig::Recorded(path) => run_recorded(controller.as_mut(), &read_recording(path)?),
+    };
+    let metrics = metrics(&samples, config.target, config.settling_band);
+    Ok(Benchmark { metrics, samples })
+}
+
+pub fn run_vessel(
+    controller: &mut dyn Control,
+    mut model: VesselModel,
+    sample_time: f32,
+    duration: f32,
+) -> Vec<Sample> {
+    let num_samples = (duration / sample_time).ceil() as usize;
+    (0..num_samples)
+        .map(|idx| {
+            let time = idx as f32 * sample_time;
+            let measurement = model.temperature();
+            let signal =
+                controller.calculate_signal(Some(measurement), TimeStamp(0).add_secs(time));
+            model.step(signal, sample_time);
+            Sample {
+                time,
+                measurement,
+                signal,
+            }
+        })
+        .collect()
+}
+
+pub fn run_recorded(controller: &mut dyn Control, recording: &[(f32, f32)]) -> Vec<Sample> {
+    recording
+        .iter()
+        .map(|(time, measurement)| Sample {
+            time: *time,
+            measurement: *measurement,
+            signal: controller.calculate_signal(Some(*measurement), TimeStamp(0).add_secs(*time)),
+        })
+        .collect()
+}
+
+fn read_recording(path: &Path) -> Result<Vec<(f32, f32)>, BenchmarkError> {
+    let content = fs::read_to_string(path)?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            let time = fields.next().and_then(|field| field.parse().ok());
+            let measurement = fields.next().and_then(|field| field.parse().ok());
+            match (time, measurement) {
+                (Some(time), Some(measurement)) => Ok((time, measurement)),
+                _ => Err(BenchmarkError::Parse(format!("Invalid line: '{}'", line))),
+            }
+        })
+        .collect()
+}
+
+pub fn metrics(samples: &[Sample], target: f32, settling_band: f32) -> Metrics {
+    let initial = samples.first().map_or(target, |sample| sample.measurement);
+    let step = target - initial;
+    let direction = if step < 0.0 { -1.0 } else { 1.0 };
+    // Progress along the step, 0 at the start and 1 at the target.
+    let progress = |measurement: f32| {
+        if step == 0.0 {
+            1.0
+        } else {
+            (measurement - initial) / step
+        }
+    };
+
+    let overshoot = samples
+        .iter()
+        .map(|sample| direction * (sample.measurement - target))
+        .fold(0.0, f32::max);
+
+    let low = samples
+        .iter()
+        .find(|sample| progress(sample.measurement) >= 0.1);
+    let high = samples
+        .iter()
+        .find(|sample| progress(sample.measurement) >= 0.9);
+    let rise_time = match (low, high) {
+        (Some(low), Some(high)) => Some(high.time - low.time),
+        _ => None,
+    };
+
+    let settling_time = match samples
+        .iter()
+        .rposition(|sample| (sample.measurement - target).abs() > settling_band)
+    {
+        None => samples.first().map(|sample| sample.time),
+        Some(idx) => samples.get(idx + 1).map(|sample| sample.time),
+    };
+
+    let iae = samples
+        .windows(2)
+        .map(|pair| (pair[0].measurement - target).abs() * (pair[1].time - pair[0].time))
+        .sum();
+
+    let switch_count = samples
+        .windows(2)
+        .filter(|pair| actuation(pair[0].signal) != actuation(pair[1].signal))
+        .count();
+
+    Metrics {
+        overshoot,
+        rise_time,
+        settling_time,
+        iae,
+        switch_count,
+    }
+}
+
+/// Heating, idle or cooling.
+fn actuation(signal: f32) -> i8 {
+    if signal > 0.0 {
+        1
+    } else if signal < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BenchmarkError {
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Controller error: {0}")]
+    Controller(#[from] ControllerError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Parse error: {0}")]
+    Parse(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::hysteresis;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn sample(time: f32, measurement: f32, signal: f32) -> Sample {
+        Sample {
+            time,
+            measurement,
+            signal,
+        }
+    }
+
+    fn vessel(dead_time: f32) -> VesselModel {
+        VesselModel::new(
+            VesselParams {
+                volume: 20.0,
+                heater_power: 3000.0,
+                heat_loss: 10.0,
+                dead_time,
+                ambient: 20.0,
+            },
+            20.0,
+        )
+    }
+
+    #[test]
+    fn test_metrics() {
+        let samples = vec![
+            sample(0.0, 20.0, 1.0),
+            sample(1.0, 24.0, 1.0),
+            sample(2.0, 29.0, 0.0),
+            sample(3.0, 31.0, 0.0),
+            sample(4.0, 30.2, 1.0),
+            sample(5.0, 30.0, 0.0),
+        ];
+        let metrics = metrics(&samples, 30.0, 0.5);
+        assert_approx_eq!(metrics.overshoot, 1.0);
+        assert_approx_eq!(metrics.rise_time.unwrap(), 1.0);
+        assert_approx_eq!(metrics.settling_time.unwrap(), 4.0);
+        assert_approx_eq!(metrics.iae, 10.0 + 6.0 + 1.0 + 1.0 + 0.2);
+        assert_eq!(metrics.switch_count, 3);
+    }
+
+    #[test]
+    fn test_metrics_cooling_step() {
+        let samples = vec![sample(0.0, 20.0, -1.0), sample(1.0, 17.0, 0.0)];
+        let metrics = metrics(&samples, 18.0, 0.5);
+        assert_approx_eq!(metrics.overshoot, 1.0);
+        assert!(metrics.settling_time.is_none());
+    }
+
+    #[test]
+    fn test_read_recording() {
+        let path = std::env::temp_dir().join(format!("bryggio-rec-{}.csv", std::process::id()));
+        fs::write(&path, "# time,measurement\n0.0, 20.0\n1.0,21.5\n\n").unwrap();
+        assert_eq!(
+            read_recording(&path).unwrap(),
+            vec![(0.0, 20.0), (1.0, 21.5)]
+        );
+        fs::write(&path, "0.0;20.0\n").unwrap();
+        assert!(read_recording(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hysteresis_against_vessel() {
+        let mut controller = hysteresis::Controller::try_new(65.0, 1.0, 0.0).unwrap();
+        let samples = run_vessel(&mut controller, vessel(0.0), 1.0, 7200.0);
+        let metrics = metrics(&samples, 65.0, 1.5);
+        assert!(metrics.overshoot < 0.1);
+        assert!(metrics.settling_time.is_some());
+        assert!(metrics.switch_count > 2);
+    }
+
+    #[test]
+    fn test_dead_time_gives_overshoot() {
+        let mut controller = hysteresis::Controller::try_new(65.0, 1.0, 0.0).unwrap();
+        let samples = run_vessel(&mut controller, vessel(60.0), 1.0, 7200.0);
+        let metrics = metrics(&samples, 65.0, 1.0);
+        assert!(metrics.overshoot > 1.0);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::pub_sub::{ClientId, Subject};
+use crate::sensor::SensorMsg;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FusionConfig {
+    pub(crate) sensor_ids: Vec<ClientId>,
+    #[serde(default)]
+    pub(crate) strategy: FusionStrategy,
+    /// Max. distance to the median of all probes, for a probe to be used.
+    #[serde(default)]
+    pub(crate) tolerance: Option<f32>,
+    /// Max. age of a measurement, relative to the newest one, for it to be used.
+    #[serde(default = "default_max_skew_ms")]
+    pub(crate) max_skew_ms: u64,
+}
+
+fn default_max_skew_ms() -> u64 {
+    5000
+}
+
+impl FusionConfig {
+    pub fn single(sensor_id: ClientId) -> Self {
+        FusionConfig {
+            sensor_ids: vec![sensor_id],
+            strategy: FusionStrategy::default(),
+            tolerance: None,
+            max_skew_ms: default_max_skew_ms(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FusionStrategy {
+    #[serde(rename = "mean")]
+    Mean,
+    #[serde(rename = "median")]
+    Median,
+    #[serde(rename = "min")]
+    Min,
+    #[serde(rename = "max")]
+    Max,
+    /// Weighted mean, probes without a weight get weight 1.
+    #[serde(rename = "weighted")]
+    Weighted(HashMap<ClientId, f32>),
+}
+
+impl Default for FusionStrategy {
+    fn default() -> Self {
+        FusionStrategy::Mean
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FusedMeasurement {
+    pub value: f32,
+    /// Time of the newest measurement used.
+    pub timestamp: TimeStamp,
+    pub sensors: Vec<ClientId>,
+}
+
+/// Combines the latest measurements of several probes into one.
+pub struct SensorFusion {
+    config: FusionConfig,
+    latest: HashMap<ClientId, (TimeStamp, f32)>,
+}
+
+impl SensorFusion {
+    pub fn new(config: FusionConfig) -> Self {
+        SensorFusion {
+            config,
+            latest: HashMap::new(),
+        }
+    }
+
+    pub fn sensor_ids(&self) -> &[ClientId] {
+        &self.config.sensor_ids
+    }
+
+    /// Subject with the measurements of all fused sensors.
+    pub fn subject(&self) -> Subject {
+        match self.config.sensor_ids.as_slice() {
+            [sensor_id] => SensorMsg::subject(sensor_id),
+            _ => Subject(String::from("sensor.*.measurement")),
+        }
+    }
+
+    /// Store a measurement. Failed measurements remove the probe until it recovers.
+    pub fn update(&mut self, msg: SensorMsg) {
+        if !self.config.sensor_ids.contains(&msg.id) {
+            return;
+        }
+        match msg.meas {
+            Ok(meas) => {
+                self.latest.insert(msg.id, (msg.timestamp, meas));
+            }
+            Err(_) => {
+                self.latest.remove(&msg.id);
+            }
+        }
+    }
+
+    pub fn fused(&self) -> Option<FusedMeasurement> {
+        let newest = self
+            .latest
+            .values()
+            .map(|(timestamp, _)| *timestamp)
+            .max()?;
+        let max_skew = u128::from(self.config.max_skew_ms);
+        let mut aligned: Vec<(&ClientId, TimeStamp, f32)> = self
+            .config
+            .sensor_ids
+            .iter()
+            .filter_map(|id| match self.latest.get(id) {
+                Some((timestamp, meas)) if newest.0 - timestamp.0 <= max_skew => {
+                    Some((id, *timestamp, *meas))
+                }
+                _ => None,
+            })
+            .collect();
+
+        if let Some(tolerance) = self.config.tolerance {
+            let values: Vec<f32> = aligned.iter().map(|(_, _, meas)| *meas).collect();
+            let median = median(&values);
+            aligned.retain(|(_, _, meas)| (meas - median).abs() <= tolerance);
+        }
+        if aligned.is_empty() {
+            return None;
+        }
+
+        let values: Vec<f32> = aligned.iter().map(|(_, _, meas)| *meas).collect();
+        let value = match &self.config.strategy {
+            FusionStrategy::Mean => values.iter().sum::<f32>() / values.len() as f32,
+            FusionStrategy::Median => median(&values),
+            FusionStrategy::Min => values.iter().cloned().fold(f32::INFINITY, f32::min),
+            FusionStrategy::Max => values.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+            FusionStrategy::Weighted(weights) => {
+                let (weighted_sum, total_weight) = aligned.iter().fold(
+                    (0.0, 0.0),
+                    |(weighted_sum, total_weight), (id, _, meas)| {
+                        let weight = weights.get(id).cloned().unwrap_or(1.0);
+                        (weighted_sum + weight * meas, total_weight + weight)
+                    },
+                );
+                if total_weight <= 0.0 {
+                    return None;
+                }
+                weighted_sum / total_weight
+            }
+        };
+        Some(FusedMeasurement {
+            value,
+            timestamp: aligned
+                .iter()
+                .map(|(_, timestamp, _)| *timestamp)
+                .max()
+                .unwrap_or(newest),
+            sensors: aligned.into_iter().map(|(id, _, _)| id.clone()).collect(),
+        })
+    }
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::SensorError;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn fusion(strategy: FusionStrategy, tolerance: Option<f32>) -> SensorFusion {
+        SensorFusion::new(FusionConfig {
+            sensor_ids: vec!["a".into(), "b".into(), "c".into()],
+            strategy,
+            tolerance,
+            max_skew_ms: 1000,
+        })
+    }
+
+    fn msg(id: &str, timestamp: u128, meas: f32) -> SensorMsg {
+        SensorMsg {
+            id: id.into(),
+            timestamp: TimeStamp(timestamp),
+            meas: Ok(meas),
+        }
+    }
+
+    fn update_all(fusion: &mut SensorFusion) {
+        fusion.update(msg("a", 0, 60.0));
+        fusion.update(msg("b", 100, 62.0));
+        fusion.update(msg("c", 200, 67.0));
+    }
+
+    #[test]
+    fn test_strategies() {
+        let cases = vec![
+            (FusionStrategy::Mean, 63.0),
+            (FusionStrategy::Median, 62.0),
+            (FusionStrategy::Min, 60.0),
+            (FusionStrategy::Max, 67.0),
+            (
+                FusionStrategy::Weighted(
+                    vec![("a".into(), 3.0), ("c".into(), 0.0)]
+                        .into_iter()
+                        .collect(),
+                ),
+                60.5,
+            ),
+        ];
+        for (strategy, expected) in cases {
+            let mut fusion = fusion(strategy, None);
+            update_all(&mut fusion);
+            assert_approx_eq!(fusion.fused().unwrap().value, expected);
+        }
+    }
+
+    #[test]
+    fn test_reject_outlier() {
+        let mut fusion = fusion(FusionStrategy::Mean, Some(2.5));
+        update_all(&mut fusion);
+        let fused = fusion.fused().unwrap();
+        assert_approx_eq!(fused.value, 61.0);
+        assert_eq!(fused.timestamp, TimeStamp(100));
+        assert_eq!(
+            fused.sensors,
+            vec![ClientId::from("a"), ClientId::from("b")]
+        );
+    }
+
+    #[test]
+    fn test_align_by_timestamp() {
+        let mut fusion = fusion(FusionStrategy::Mean, None);
+        update_all(&mut fusion);
+        fusion.update(msg("c", 1050, 68.0));
+        let fused = fusion.fused().unwrap();
+        assert_approx_eq!(fused.value, 65.0);
+        assert_eq!(
+            fused.sensors,
+            vec![ClientId::from("b"), ClientId::from("c")]
+        );
+    }
+
+    #[test]
+    fn test_failed_measurement() {
+        let mut fusion = fusion(FusionStrategy::Mean, None);
+        fusion.update(msg("a", 0, 60.0));
+        fusion.update(SensorMsg {
+            id: "a".into(),
+            timestamp: TimeStamp(100),
+            meas: Err(SensorError::Read(String::from("Gone"))),
+        });
+        assert!(fusion.fused().is_none());
+    }
+
+    #[test]
+    fn test_ignore_other_sensors() {
+        let mut fusion = fusion(FusionStrategy::Mean, None);
+        fusion.update(msg("d", 0, 60.0));
+        assert!(fusion.fused().is_none());
+    }
+}
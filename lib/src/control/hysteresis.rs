@@ -1,10 +1,10 @@
 use crate::control;
+use crate::time::TimeStamp;
 use std::f32;
 
 pub struct Controller {
     pub target: f32,
     pub current_signal: f32,
-    previous_measurement: Option<f32>,
     pub state: control::State,
     offset_on: f32,
     offset_off: f32,
@@ -21,7 +21,6 @@ impl Controller {
                 Ok(Controller {
                     target,
                     current_signal: 0.0,
-                    previous_measurement: None,
                     state: control::State::Active,
                     offset_on,
                     offset_off,
@@ -42,14 +41,7 @@ impl Controller {
 }
 
 impl control::Control for Controller {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32 {
-        let measurement = match measurement {
-            Some(measurement) => Some(measurement),
-            None => match self.previous_measurement {
-                Some(previous_measurement) => Some(previous_measurement),
-                None => None,
-            },
-        };
+    fn calculate_signal(&mut self, measurement: Option<f32>, _timestamp: TimeStamp) -> f32 {
         match measurement {
             Some(measurement) => {
                 let diff = self.target - measurement;
@@ -59,10 +51,11 @@ impl control::Control for Controller {
                     self.current_signal = 0.0;
                 } else {
                 }
-                self.current_signal
             }
-            None => self.current_signal,
+            // Without a valid measurement, the relay is not kept on blindly.
+            None => self.current_signal = 0.0,
         }
+        self.current_signal
     }
 
     fn get_state(&self) -> control::State {
@@ -84,6 +77,11 @@ impl control::Control for Controller {
     fn get_control_signal(&self) -> f32 {
         self.current_signal
     }
+
+    fn transfer_from(&mut self, signal: f32) {
+        // Keeps the relay in the same position until the measurement leaves the band.
+        self.current_signal = if signal > 0.0 { 1.0 } else { 0.0 };
+    }
 }
 
 #[cfg(test)]
@@ -120,21 +118,21 @@ mod tests {
     fn test_control_under() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(90.0)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(90.0), TimeStamp(0)), 1.0);
     }
 
     #[test]
     fn test_control_ower() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(110.0)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(110.0), TimeStamp(0)), 0.0);
     }
 
     #[test]
     fn test_control_ower_offset_on() {
         let mut controller = Controller::try_new(0.0, 2.0, 1.0).unwrap();
         controller.set_target(100.0);
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 0.0);
     }
 
     #[test]
@@ -143,12 +141,19 @@ mod tests {
         controller.set_target(100.0);
 
         // Make sure controller.current_signal is 100.0
-        assert_approx_eq!(controller.calculate_signal(Some(30.0)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(30.0), TimeStamp(0)), 1.0);
         // Make sure controller.current_signal remains
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 1.0);
         // Make sure controller.current_signal is switched to 0.0
-        assert_approx_eq!(controller.calculate_signal(Some(99.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(99.5), TimeStamp(0)), 0.0);
         // Make sure controller.current_signal remains
-        assert_approx_eq!(controller.calculate_signal(Some(98.5)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(Some(98.5), TimeStamp(0)), 0.0);
+    }
+
+    #[test]
+    fn test_missing_measurement() {
+        let mut controller = Controller::try_new(100.0, 2.0, 1.0).unwrap();
+        assert_approx_eq!(controller.calculate_signal(Some(90.0), TimeStamp(0)), 1.0);
+        assert_approx_eq!(controller.calculate_signal(None, TimeStamp(1000)), 0.0);
     }
 }
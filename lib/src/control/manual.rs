@@ -1,4 +1,5 @@
 use crate::control;
+use crate::time::TimeStamp;
 use std::f32;
 
 pub struct Controller {
@@ -18,7 +19,7 @@ impl Controller {
 }
 
 impl control::Control for Controller {
-    fn calculate_signal(&mut self, _measurement: Option<f32>) -> f32 {
+    fn calculate_signal(&mut self, _measurement: Option<f32>, _timestamp: TimeStamp) -> f32 {
         self.current_signal = self.target;
         self.current_signal
     }
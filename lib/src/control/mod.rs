@@ -1,22 +1,46 @@
+use crate::actor;
 use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::f32;
 use thiserror::Error;
 
-pub mod duty_cycle;
+pub mod autotune;
+pub mod benchmark;
+pub mod fusion;
 pub mod hysteresis;
 pub mod manual;
-// pub mod pid;
+pub mod pid;
 pub mod pub_sub;
+pub mod ramp;
+pub mod split_range;
 pub use pub_sub::ControllerClient;
 
 pub trait Control: Send {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32;
+    /// New signal from a measurement taken at `timestamp`.
+    /// Without a measurement, `timestamp` is the time of the call.
+    ///
+    /// Time steps, e.g. for integration, are the time between the timestamps of two calls.
+    /// A measurement may be passed again, with the same timestamp, until a newer one arrives.
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32;
     fn get_state(&self) -> State;
     fn set_state(&mut self, new_state: State);
     fn get_control_signal(&self) -> f32;
     fn get_target(&self) -> f32;
     fn set_target(&mut self, new_target: f32);
+    /// Target currently used for control, which may lag the one set with `set_target`.
+    fn get_effective_target(&self) -> f32 {
+        self.get_target()
+    }
+    /// Take over from a controller which produced `signal`, without a jump in the output.
+    fn transfer_from(&mut self, _signal: f32) {}
+    /// Continue after a gap in control, e.g. an override, without integrating over the gap.
+    fn resume(&mut self) {}
+    /// Suggested gains, available once an auto-tuning experiment has finished.
+    fn tuning_result(&self) -> Option<autotune::TuningResult> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -33,31 +57,242 @@ pub enum ControllerType {
     Hysteresis { offset_on: f32, offset_off: f32 },
     #[serde(rename = "manual")]
     Manual,
+    #[serde(rename = "pid")]
+    Pid { kp: f32, ki: f32, kd: f32 },
+    #[serde(rename = "auto_tune")]
+    AutoTune {
+        offset_on: f32,
+        offset_off: f32,
+        cycles: usize,
+    },
+    /// Heating and cooling, for a split range output.
+    #[serde(rename = "split_range")]
+    SplitRange { deadband: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ControllerOutput {
+    #[serde(rename = "actor_id")]
+    Actor(ClientId),
+    #[serde(rename = "cascade")]
+    Cascade(CascadeConfig),
+    #[serde(rename = "split_range")]
+    SplitRange(SplitRangeConfig),
+}
+
+impl ControllerOutput {
+    pub fn client_ids(&self) -> Vec<&ClientId> {
+        match self {
+            ControllerOutput::Actor(actor_id) => vec![actor_id],
+            ControllerOutput::Cascade(cascade) => vec![&cascade.controller_id],
+            ControllerOutput::SplitRange(split_range) => {
+                vec![&split_range.heater_id, &split_range.cooler_id]
+            }
+        }
+    }
+
+    /// Actors driven directly by the controller, which only accept signals from it while leased.
+    pub fn actor_ids(&self) -> Vec<&ClientId> {
+        match self {
+            ControllerOutput::Actor(actor_id) => vec![actor_id],
+            ControllerOutput::Cascade(_) => Vec::new(),
+            ControllerOutput::SplitRange(split_range) => {
+                vec![&split_range.heater_id, &split_range.cooler_id]
+            }
+        }
+    }
+
+    fn signal_bounds(&self) -> (f32, f32) {
+        match self {
+            ControllerOutput::Actor(_) => (actor::SIGNAL_LOWER_BOUND, actor::SIGNAL_UPPER_BOUND),
+            ControllerOutput::Cascade(cascade) => {
+                (cascade.min - cascade.offset, cascade.max - cascade.offset)
+            }
+            ControllerOutput::SplitRange(_) => {
+                (-actor::SIGNAL_UPPER_BOUND, actor::SIGNAL_UPPER_BOUND)
+            }
+        }
+    }
+}
+
+/// Cascade control, where the signal of the outer controller sets the target of an inner one.
+///
+/// The inner target is the outer signal plus `offset`, clamped to `[min, max]`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CascadeConfig {
+    pub(crate) controller_id: ClientId,
+    #[serde(default)]
+    pub(crate) offset: f32,
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+}
+
+impl CascadeConfig {
+    pub fn inner_target(&self, signal: f32) -> f32 {
+        (signal + self.offset).clamp(self.min, self.max)
+    }
+}
+
+/// Measurements to control on, either a single sensor or several fused probes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ControllerInput {
+    #[serde(rename = "sensor_id")]
+    Sensor(ClientId),
+    #[serde(rename = "sensors")]
+    Fusion(fusion::FusionConfig),
+}
+
+impl ControllerInput {
+    pub fn sensor_ids(&self) -> &[ClientId] {
+        match self {
+            ControllerInput::Sensor(sensor_id) => std::slice::from_ref(sensor_id),
+            ControllerInput::Fusion(fusion) => &fusion.sensor_ids,
+        }
+    }
+
+    pub fn fusion(&self) -> fusion::SensorFusion {
+        match self {
+            ControllerInput::Sensor(sensor_id) => {
+                fusion::SensorFusion::new(fusion::FusionConfig::single(sensor_id.clone()))
+            }
+            ControllerInput::Fusion(fusion) => fusion::SensorFusion::new(fusion.clone()),
+        }
+    }
 }
 
+/// Heating and cooling actors driven from one signal in `[-1, 1]`.
+///
+/// Positive signals go to the heater and negative ones, negated, to the cooler.
+/// The other actor is always off. The actors must also be in an exclusive interlock,
+/// so that they are never on at once, whatever order the signals arrive in.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SplitRangeConfig {
+    pub(crate) heater_id: ClientId,
+    pub(crate) cooler_id: ClientId,
+}
+
+impl SplitRangeConfig {
+    /// Heater and cooler signals.
+    pub fn actor_signals(&self, signal: f32) -> (f32, f32) {
+        (
+            signal.max(actor::SIGNAL_LOWER_BOUND),
+            (-signal).max(actor::SIGNAL_LOWER_BOUND),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "UncheckedControllerConfig")]
 pub struct ControllerConfig {
     pub(crate) controller_id: ClientId,
-    pub(crate) actor_id: ClientId,
-    pub(crate) sensor_id: ClientId,
+    #[serde(flatten)]
+    pub(crate) output: ControllerOutput,
+    #[serde(flatten)]
+    pub(crate) input: ControllerInput,
     #[serde(rename = "type")]
     pub(crate) type_: ControllerType,
+    /// Max. rate of change of the target in °C/min.
+    #[serde(default)]
+    pub(crate) ramp_rate: Option<f32>,
+    /// Time without valid measurements, after which the output is set to a safe state.
+    #[serde(default = "default_stale_timeout_ms")]
+    pub(crate) stale_timeout_ms: u64,
+    /// Period of the control loop, independent of how often the sensors publish.
+    #[serde(default = "default_update_period_ms")]
+    pub(crate) update_period_ms: u64,
+    /// Min. time between status updates, unless the target changes.
+    #[serde(default = "default_status_period_ms")]
+    pub(crate) status_period_ms: u64,
+}
+
+/// Controller config as parsed, before the type is checked against the output.
+#[derive(Deserialize)]
+struct UncheckedControllerConfig {
+    controller_id: ClientId,
+    #[serde(flatten)]
+    output: ControllerOutput,
+    #[serde(flatten)]
+    input: ControllerInput,
+    #[serde(rename = "type")]
+    type_: ControllerType,
+    #[serde(default)]
+    ramp_rate: Option<f32>,
+    #[serde(default = "default_stale_timeout_ms")]
+    stale_timeout_ms: u64,
+    #[serde(default = "default_update_period_ms")]
+    update_period_ms: u64,
+    #[serde(default = "default_status_period_ms")]
+    status_period_ms: u64,
+}
+
+impl TryFrom<UncheckedControllerConfig> for ControllerConfig {
+    type Error = ControllerError;
+
+    fn try_from(config: UncheckedControllerConfig) -> Result<Self, Self::Error> {
+        match (&config.type_, &config.output) {
+            (ControllerType::SplitRange { .. }, ControllerOutput::SplitRange(_)) => {}
+            (ControllerType::SplitRange { .. }, _) => {
+                return Err(ControllerError::Type(format!(
+                    "split range controller '{}' needs a split range output",
+                    config.controller_id
+                )))
+            }
+            _ => {}
+        }
+        Ok(ControllerConfig {
+            controller_id: config.controller_id,
+            output: config.output,
+            input: config.input,
+            type_: config.type_,
+            ramp_rate: config.ramp_rate,
+            stale_timeout_ms: config.stale_timeout_ms,
+            update_period_ms: config.update_period_ms,
+            status_period_ms: config.status_period_ms,
+        })
+    }
+}
+
+fn default_stale_timeout_ms() -> u64 {
+    60_000
+}
+
+fn default_update_period_ms() -> u64 {
+    1000
+}
+
+fn default_status_period_ms() -> u64 {
+    5000
 }
 
 impl ControllerConfig {
     pub fn dummy() -> Self {
         ControllerConfig {
             controller_id: ClientId("controller".into()),
-            actor_id: ClientId("mash_heater".into()),
-            sensor_id: ClientId("mash_temp".into()),
+            output: ControllerOutput::Actor(ClientId("mash_heater".into())),
+            input: ControllerInput::Sensor(ClientId("mash_temp".into())),
             type_: ControllerType::Manual,
+            ramp_rate: None,
+            stale_timeout_ms: default_stale_timeout_ms(),
+            update_period_ms: default_update_period_ms(),
+            status_period_ms: default_status_period_ms(),
         }
     }
     pub fn client_ids(&self) -> impl Iterator<Item = &ClientId> {
-        std::iter::once(&self.actor_id).chain(std::iter::once(&self.sensor_id))
+        self.output
+            .client_ids()
+            .into_iter()
+            .chain(self.input.sensor_ids())
     }
 
     pub fn get_controller(&self, target: f32) -> Result<Box<dyn Control>, ControllerError> {
+        let controller = self.get_base_controller(target)?;
+        match self.ramp_rate {
+            Some(rate) => Ok(Box::new(ramp::Controller::try_new(controller, rate)?)),
+            None => Ok(controller),
+        }
+    }
+
+    fn get_base_controller(&self, target: f32) -> Result<Box<dyn Control>, ControllerError> {
         match self.type_ {
             ControllerType::Hysteresis {
                 offset_on,
@@ -67,7 +302,24 @@ impl ControllerConfig {
                 Ok(Box::new(control))
             }
             ControllerType::Manual { .. } => Ok(Box::new(manual::Controller::new(target))),
-            //_ => unimplemented!(),
+            ControllerType::Pid { kp, ki, kd } => {
+                let (lower_bound, upper_bound) = self.output.signal_bounds();
+                let control =
+                    pid::Controller::try_new(target, kp, ki, kd, lower_bound, upper_bound)?;
+                Ok(Box::new(control))
+            }
+            ControllerType::AutoTune {
+                offset_on,
+                offset_off,
+                cycles,
+            } => {
+                let control = autotune::Controller::try_new(target, offset_on, offset_off, cycles)?;
+                Ok(Box::new(control))
+            }
+            ControllerType::SplitRange { deadband } => {
+                let control = split_range::Controller::try_new(target, deadband)?;
+                Ok(Box::new(control))
+            }
         }
     }
 }
@@ -79,3 +331,125 @@ pub enum ControllerError {
     #[error("Unknown type: {0}")]
     Type(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_parse_actor_output() {
+        let config: ControllerConfig = serde_json::from_str(
+            r#"
+            {
+              "controller_id": "mash",
+              "actor_id": "mash_heater",
+              "sensor_id": "mash_temp",
+              "type": "manual"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.output.client_ids(),
+            vec![&ClientId("mash_heater".into())]
+        );
+        assert_eq!(config.stale_timeout_ms, 60_000);
+        assert_eq!(config.update_period_ms, 1000);
+        assert_eq!(config.status_period_ms, 5000);
+    }
+
+    #[test]
+    fn test_parse_cascade_output() {
+        let config: ControllerConfig = serde_json::from_str(
+            r#"
+            {
+              "controller_id": "mash",
+              "cascade": {"controller_id": "hlt", "offset": 5.0, "min": 20.0, "max": 80.0},
+              "sensor_id": "mash_temp",
+              "type": "manual"
+            }"#,
+        )
+        .unwrap();
+        match config.output {
+            ControllerOutput::Cascade(cascade) => {
+                assert_eq!(cascade.controller_id, ClientId("hlt".into()));
+                assert_approx_eq!(cascade.inner_target(65.0), 70.0);
+                assert_approx_eq!(cascade.inner_target(90.0), 80.0);
+            }
+            _ => panic!("Expected cascade output"),
+        }
+    }
+
+    #[test]
+    fn test_parse_split_range_output() {
+        let config: ControllerConfig = serde_json::from_str(
+            r#"
+            {
+              "controller_id": "fermenter",
+              "split_range": {"heater_id": "heat_belt", "cooler_id": "fridge"},
+              "sensor_id": "wort_temp",
+              "type": {"split_range": {"deadband": 0.5}}
+            }"#,
+        )
+        .unwrap();
+        match config.output {
+            ControllerOutput::SplitRange(split_range) => {
+                assert_eq!(split_range.actor_signals(1.0), (1.0, 0.0));
+                assert_eq!(split_range.actor_signals(-0.5), (0.0, 0.5));
+                assert_eq!(split_range.actor_signals(0.0), (0.0, 0.0));
+            }
+            _ => panic!("Expected split range output"),
+        }
+
+        let config: Result<ControllerConfig, _> = serde_json::from_str(
+            r#"
+            {
+              "controller_id": "fermenter",
+              "actor_id": "heat_belt",
+              "sensor_id": "wort_temp",
+              "type": {"split_range": {"deadband": 0.5}}
+            }"#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_parse_fused_input() {
+        let config: ControllerConfig = serde_json::from_str(
+            r#"
+            {
+              "controller_id": "mash",
+              "actor_id": "mash_heater",
+              "sensors": {
+                "sensor_ids": ["mash_top", "mash_bottom"],
+                "strategy": {"weighted": {"mash_bottom": 2.0}},
+                "tolerance": 3.0
+              },
+              "type": "manual"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.client_ids().collect::<Vec<_>>(),
+            vec![
+                &ClientId("mash_heater".into()),
+                &ClientId("mash_top".into()),
+                &ClientId("mash_bottom".into())
+            ]
+        );
+        match config.input {
+            ControllerInput::Fusion(fusion) => {
+                assert_eq!(
+                    fusion.strategy,
+                    fusion::FusionStrategy::Weighted(
+                        vec![(ClientId("mash_bottom".into()), 2.0)]
+                            .into_iter()
+                            .collect()
+                    )
+                );
+                assert_eq!(fusion.max_skew_ms, 5000);
+            }
+            ControllerInput::Sensor(_) => panic!("Expected fused input"),
+        }
+    }
+}
@@ -1,42 +1,102 @@
 use crate::control;
-use pid as ext_pid;
+use crate::time::TimeStamp;
 use std::f32;
 
 pub struct Controller {
     pub target: f32,
     pub current_signal: f32,
     state: control::State,
-    pid: ext_pid::Pid<f32>,
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    lower_bound: f32,
+    upper_bound: f32,
+    integral: f32,
+    previous_measurement: Option<f32>,
+    previous_time: Option<TimeStamp>,
+    /// Signal of a replaced controller, consumed by the next calculation.
+    transfer_signal: Option<f32>,
 }
 
 impl Controller {
-    pub fn new(
+    pub fn try_new(
+        target: f32,
         kp: f32,
         ki: f32,
         kd: f32,
-        p_limit: Option<f32>,
-        i_limit: Option<f32>,
-        d_limit: Option<f32>,
-    ) -> Controller {
-        let p_limit = p_limit.unwrap_or(100.0);
-        let i_limit = i_limit.unwrap_or(100.0);
-        let d_limit = d_limit.unwrap_or(100.0);
-        let pid = ext_pid::Pid::new(kp, ki, kd, p_limit, i_limit, d_limit, 0.0);
-        Controller {
-            target: 0.0,
-            current_signal: 0.0,
+        lower_bound: f32,
+        upper_bound: f32,
+    ) -> Result<Controller, control::ControllerError> {
+        if kp < 0.0 || ki < 0.0 || kd < 0.0 {
+            return Err(control::ControllerError::ParamError(format!(
+                "PID gains must be non-negative (kp: {}, ki: {}, kd: {})",
+                kp, ki, kd
+            )));
+        }
+        if lower_bound >= upper_bound {
+            return Err(control::ControllerError::ParamError(format!(
+                "Lower output bound must be less than the upper ({} !< {})",
+                lower_bound, upper_bound
+            )));
+        }
+        Ok(Controller {
+            target,
+            current_signal: lower_bound,
             state: control::State::Active,
-            pid,
+            kp,
+            ki,
+            kd,
+            lower_bound,
+            upper_bound,
+            integral: 0.0,
+            previous_measurement: None,
+            previous_time: None,
+            transfer_signal: None,
+        })
+    }
+
+    fn update(&mut self, measurement: f32, dt: f32) -> f32 {
+        let error = self.target - measurement;
+        let proportional = self.kp * error;
+        // Derivative on measurement, so that target changes do not kick the signal.
+        let derivative = match self.previous_measurement {
+            Some(previous) if dt > 0.0 => -self.kd * (measurement - previous) / dt,
+            _ => 0.0,
+        };
+        self.previous_measurement = Some(measurement);
+
+        if let Some(signal) = self.transfer_signal.take() {
+            // Bumpless transfer: pick the integral which reproduces the previous signal.
+            self.integral = signal - proportional - derivative;
+        } else {
+            let unclamped = proportional + self.integral + derivative;
+            // Anti-windup: do not integrate further into an already saturated output.
+            let saturated_high = unclamped >= self.upper_bound && error > 0.0;
+            let saturated_low = unclamped <= self.lower_bound && error < 0.0;
+            if !saturated_high && !saturated_low {
+                self.integral = (self.integral + self.ki * error * dt)
+                    .clamp(self.lower_bound, self.upper_bound);
+            }
         }
+
+        self.current_signal =
+            (proportional + self.integral + derivative).clamp(self.lower_bound, self.upper_bound);
+        self.current_signal
     }
 }
 
 impl control::Control for Controller {
-    fn calculate_signal(&mut self, measurement: Option<f32>) -> f32 {
-        if let Some(measurement) = measurement {
-            self.pid.next_control_output(measurement).output
-        } else {
-            self.current_signal
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        match (measurement, self.previous_time) {
+            // Integral and derivative use the time between measurements,
+            // and a measurement which is not newer than the previous one adds nothing.
+            (Some(_), Some(previous_time)) if timestamp <= previous_time => self.current_signal,
+            (Some(measurement), previous_time) => {
+                let dt = previous_time.map_or(0.0, |previous| timestamp.secs_since(previous));
+                self.previous_time = Some(timestamp);
+                self.update(measurement, dt)
+            }
+            (None, _) => self.current_signal,
         }
     }
 
@@ -59,4 +119,105 @@ impl control::Control for Controller {
     fn get_target(&self) -> f32 {
         self.target
     }
+
+    fn transfer_from(&mut self, signal: f32) {
+        let signal = signal.clamp(self.lower_bound, self.upper_bound);
+        self.current_signal = signal;
+        self.transfer_signal = Some(signal);
+    }
+
+    fn resume(&mut self) {
+        self.previous_measurement = None;
+        self.previous_time = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::Control;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_constructor_neg_gain() {
+        let controller = Controller::try_new(0.0, -1.0, 0.0, 0.0, 0.0, 1.0);
+        assert!(controller.is_err())
+    }
+
+    #[test]
+    fn test_constructor_invalid_bounds() {
+        let controller = Controller::try_new(0.0, 1.0, 0.0, 0.0, 1.0, 1.0);
+        assert!(controller.is_err())
+    }
+
+    #[test]
+    fn test_output_clamped() {
+        let mut controller = Controller::try_new(100.0, 1.0, 0.0, 0.0, 0.0, 1.0).unwrap();
+        assert_approx_eq!(controller.update(50.0, 1.0), 1.0);
+        assert_approx_eq!(controller.update(150.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_proportional() {
+        let mut controller = Controller::try_new(100.0, 0.1, 0.0, 0.0, 0.0, 1.0).unwrap();
+        assert_approx_eq!(controller.update(95.0, 1.0), 0.5);
+        assert_approx_eq!(controller.get_control_signal(), 0.5);
+    }
+
+    #[test]
+    fn test_anti_windup() {
+        let mut controller = Controller::try_new(100.0, 0.0, 0.1, 0.0, 0.0, 1.0).unwrap();
+        for _ in 0..100 {
+            controller.update(20.0, 1.0);
+        }
+        assert!(controller.integral <= 1.0);
+        // Once over the target, the signal must drop without unwinding a huge integral.
+        controller.update(110.0, 1.0);
+        assert!(controller.get_control_signal() < 1.0);
+    }
+
+    #[test]
+    fn test_bumpless_transfer() {
+        let mut controller = Controller::try_new(100.0, 0.05, 0.01, 0.0, 0.0, 1.0).unwrap();
+        controller.transfer_from(0.7);
+        assert_approx_eq!(controller.update(98.0, 1.0), 0.7);
+        // Normal control continues from the transferred signal.
+        let signal = controller.update(98.0, 1.0);
+        assert!(signal > 0.7 && signal < 0.8);
+    }
+
+    #[test]
+    fn test_irregular_sampling() {
+        let mut controller = Controller::try_new(100.0, 0.0, 0.01, 0.0, 0.0, 1.0).unwrap();
+        controller.calculate_signal(Some(90.0), TimeStamp(0));
+        assert_approx_eq!(
+            controller.calculate_signal(Some(90.0), TimeStamp(5000)),
+            0.5
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(90.0), TimeStamp(5000)),
+            0.5
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(90.0), TimeStamp(6000)),
+            0.6
+        );
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut controller = Controller::try_new(100.0, 0.0, 0.01, 0.0, 0.0, 1.0).unwrap();
+        controller.calculate_signal(Some(90.0), TimeStamp(0));
+        controller.calculate_signal(Some(90.0), TimeStamp(1000));
+        controller.resume();
+        // The gap is not integrated, only the time after it.
+        assert_approx_eq!(
+            controller.calculate_signal(Some(90.0), TimeStamp(600_000)),
+            0.1
+        );
+        assert_approx_eq!(
+            controller.calculate_signal(Some(90.0), TimeStamp(601_000)),
+            0.2
+        );
+    }
 }
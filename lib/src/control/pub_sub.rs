@@ -1,54 +1,264 @@
-use crate::actor::pub_sub::SignalMsg;
-use crate::control::ControllerType;
-use crate::control::{Control, State};
+use crate::actor::{pub_sub::SignalMsg, SIGNAL_LOWER_BOUND};
+use crate::control::autotune::TuningResult;
+use crate::control::fusion::SensorFusion;
+use crate::control::{
+    Control, ControllerConfig, ControllerOutput, ControllerType, SplitRangeConfig, State,
+};
 use crate::logger::{debug, error, info};
 use crate::pub_sub::{
     nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsConfig, ClientId,
     PubSubClient, PubSubError, PubSubMsg, Subject,
 };
 use crate::sensor::SensorMsg;
+use crate::supervisor::lease::Lease;
 use crate::supervisor::pub_sub::SupervisorPubMsg;
-use crate::time::TimeStamp;
+use crate::time::{Clock, TimeStamp};
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::time::Duration;
 
 pub struct ControllerClient {
     id: ClientId,
-    actor_id: ClientId,
-    sensor_id: ClientId,
+    output: ControllerOutput,
+    fusion: SensorFusion,
+    contributing_sensors: Vec<ClientId>,
     controller: Box<dyn Control>,
     client: NatsClient,
     type_: ControllerType,
+    stale_timeout: Duration,
+    update_period: Duration,
+    status_period: Duration,
+    clock: Box<dyn Clock>,
+    /// Leases of the output actors, which are not driven while taken over by another client.
+    leases: HashMap<ClientId, Lease>,
+    lease_subs: Vec<Subscription>,
+    /// Manual signal, applied instead of the control signal until the end time.
+    override_: Option<(f32, TimeStamp)>,
 }
 
 impl ControllerClient {
     pub fn new(
-        id: ClientId,
-        actor_id: ClientId,
-        sensor_id: ClientId,
+        contr_config: ControllerConfig,
         controller: Box<dyn Control>,
         config: &NatsConfig,
-        type_: ControllerType,
+        clock: Box<dyn Clock>,
     ) -> Self {
         let client = NatsClient::try_new(config).unwrap();
         ControllerClient {
-            id,
-            actor_id,
-            sensor_id,
+            id: contr_config.controller_id,
+            output: contr_config.output,
+            fusion: contr_config.input.fusion(),
+            contributing_sensors: Vec::new(),
             controller,
             client,
-            type_,
+            type_: contr_config.type_,
+            stale_timeout: Duration::from_millis(contr_config.stale_timeout_ms),
+            update_period: Duration::from_millis(contr_config.update_period_ms),
+            status_period: Duration::from_millis(contr_config.status_period_ms),
+            clock,
+            leases: HashMap::new(),
+            lease_subs: Vec::new(),
+            override_: None,
         }
     }
 
-    fn status_update(&self) {
-        let status_update = ControllerPubMsg::Status {
+    /// Start with the leases of the output actors, and subscribe to their updates.
+    /// This is done before the client is started, so that no lease update is missed.
+    pub fn with_leases(mut self, leases: Vec<Lease>) -> Result<Self, PubSubError> {
+        for lease in leases {
+            self.lease_subs
+                .push(self.subscribe(&Lease::subject(&lease.client_id))?);
+            self.leases.insert(lease.client_id.clone(), lease);
+        }
+        Ok(self)
+    }
+
+    fn status(&self) -> ControllerStatus {
+        let now = self.clock.now();
+        let (signal, override_remaining_ms) = match self.override_ {
+            Some((signal, until)) => (
+                signal,
+                Some((until.secs_since(now).max(0.0) * 1000.0).round() as u64),
+            ),
+            None => (self.controller.get_control_signal(), None),
+        };
+        ControllerStatus {
             id: self.id.clone(),
-            timestamp: TimeStamp::now(),
+            timestamp: now,
             target: self.controller.get_target(),
+            effective_target: self.controller.get_effective_target(),
+            signal,
+            sensors: self.contributing_sensors.clone(),
             type_: self.type_.clone(),
+            override_remaining_ms,
+        }
+    }
+
+    /// Apply a manual signal for a while, with the controller frozen in the meantime.
+    /// Returns whether the override was applied.
+    fn start_override(&mut self, override_: ControllerOverride) -> bool {
+        let (lower_bound, upper_bound) = self.output.signal_bounds();
+        if !(lower_bound..=upper_bound).contains(&override_.signal) {
+            log_error(
+                self,
+                &format!(
+                    "Override signal {} not in [{}, {}]",
+                    override_.signal, lower_bound, upper_bound
+                ),
+            );
+            return false;
+        }
+        log_info(
+            self,
+            &format!(
+                "overriding output with {} for {} ms",
+                override_.signal, override_.duration_ms
+            ),
+        );
+        let until = self
+            .clock
+            .now()
+            .add_secs(override_.duration_ms as f32 / 1000.0);
+        self.override_ = Some((override_.signal, until));
+        true
+    }
+
+    /// End the override if its time is up, returns whether it did.
+    fn end_override(&mut self, now: TimeStamp) -> bool {
+        match self.override_ {
+            Some((_, until)) if now >= until => {
+                self.override_ = None;
+                log_info(self, "override ended, resuming control");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn publish_actor_signal(&self, actor_id: &ClientId, signal: f32) -> Result<(), PubSubError> {
+        if let Some(lease) = self.leases.get(actor_id) {
+            if lease.held_by_other(&self.id, self.clock.now()) {
+                return Ok(());
+            }
+        }
+        let msg = ControllerPubMsg::SetSignal(SignalMsg {
+            id: actor_id.clone(),
+            timestamp: self.clock.now(),
+            signal,
+            moving: false,
+            unknown: false,
+            source: Some(self.id.clone()),
+        });
+        self.publish(&msg.subject(actor_id), &msg.into())
+    }
+
+    /// Publish the signals of a split range output, with the one switched off first.
+    /// The actors themselves keep both from being on at once, through an exclusive interlock.
+    fn publish_split_range_signal(
+        &self,
+        split_range: &SplitRangeConfig,
+        signal: f32,
+    ) -> Result<(), PubSubError> {
+        let (heater_signal, cooler_signal) = split_range.actor_signals(signal);
+        if heater_signal > cooler_signal {
+            self.publish_actor_signal(&split_range.cooler_id, cooler_signal)?;
+            self.publish_actor_signal(&split_range.heater_id, heater_signal)
+        } else {
+            self.publish_actor_signal(&split_range.heater_id, heater_signal)?;
+            self.publish_actor_signal(&split_range.cooler_id, cooler_signal)
+        }
+    }
+
+    /// Publish the control signal to the actor, or as the target of an inner controller.
+    fn publish_signal(&self) -> Result<(), PubSubError> {
+        match self.override_ {
+            Some((signal, _)) => self.publish_output(signal),
+            None => self.publish_output(self.controller.get_control_signal()),
+        }
+    }
+
+    fn publish_output(&self, signal: f32) -> Result<(), PubSubError> {
+        match &self.output {
+            ControllerOutput::Actor(actor_id) => self.publish_actor_signal(actor_id, signal),
+            ControllerOutput::Cascade(cascade) => {
+                let msg = ControllerSubMsg::SetTarget(cascade.inner_target(signal));
+                self.publish(
+                    &ControllerSubMsg::subject(&cascade.controller_id),
+                    &msg.into(),
+                )
+            }
+            ControllerOutput::SplitRange(split_range) => {
+                self.publish_split_range_signal(split_range, signal)
+            }
+        }
+    }
+
+    /// Turn the actors off, or set the inner controller to its lowest target.
+    fn publish_safe_signal(&self) -> Result<(), PubSubError> {
+        match &self.output {
+            ControllerOutput::Actor(actor_id) => {
+                self.publish_actor_signal(actor_id, SIGNAL_LOWER_BOUND)
+            }
+            ControllerOutput::Cascade(cascade) => {
+                let msg = ControllerSubMsg::SetTarget(cascade.min);
+                self.publish(
+                    &ControllerSubMsg::subject(&cascade.controller_id),
+                    &msg.into(),
+                )
+            }
+            ControllerOutput::SplitRange(split_range) => {
+                self.publish_split_range_signal(split_range, SIGNAL_LOWER_BOUND)
+            }
+        }
+    }
+
+    fn update_lease(&mut self, lease: Lease) {
+        let taken_over = |lease: Option<&Lease>| {
+            lease.map_or(false, |lease| {
+                lease.held_by_other(&self.id, self.clock.now())
+            })
+        };
+        if taken_over(Some(&lease)) && !taken_over(self.leases.get(&lease.client_id)) {
+            log_info(
+                self,
+                &format!(
+                    "actor '{}' taken over by '{}'",
+                    lease.client_id,
+                    lease.holder.as_ref().map_or("", |holder| holder.as_ref())
+                ),
+            );
+        }
+        self.leases.insert(lease.client_id.clone(), lease);
+    }
+
+    fn alarm(&self, active: bool, msg: String) -> Result<(), PubSubError> {
+        if active {
+            log_error(self, &msg);
+        } else {
+            log_info(self, &msg);
+        }
+        let alarm = ControllerPubMsg::Alarm {
+            id: self.id.clone(),
+            timestamp: self.clock.now(),
+            active,
+            msg,
         };
+        self.publish(&alarm.subject(&self.id), &alarm.into())
+    }
+
+    fn sensor_list(&self) -> String {
+        self.fusion
+            .sensor_ids()
+            .iter()
+            .map(|id| format!("'{}'", id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn status_update(&self) {
+        let status_update = ControllerPubMsg::Status(self.status());
         if let Err(err) = self.publish(&status_update.subject(&self.id), &status_update.into()) {
             log_error(
                 &self,
@@ -67,8 +277,16 @@ impl PubSubClient for ControllerClient {
             .subject(),
         )?;
         let controller = self.subscribe(&ControllerSubMsg::subject(&self.id))?;
-        let sensor = self.subscribe(&SensorMsg::subject(&self.sensor_id))?;
+        let override_cmd = self.subscribe(&ControllerSubMsg::override_subject(&self.id))?;
+        let sensor = self.subscribe(&self.fusion.subject())?;
+        let leases = std::mem::take(&mut self.lease_subs);
         let mut state = State::Active;
+        let mut tuning_published = false;
+        let mut last_fresh_meas = self.clock.now();
+        let mut previous_meas_time = None;
+        let mut stale = false;
+        let mut next_update = self.clock.now();
+        let mut last_status = self.clock.now();
         log_info(
             &self,
             &format!("starting contr. client: {}: {:?}", &self.id, &self.type_),
@@ -76,15 +294,22 @@ impl PubSubClient for ControllerClient {
         self.status_update();
         while state == State::Active {
             if let Some(msg) = kill_cmd.try_next() {
-                // TODO: Proper Status PubMsg.
                 log_info(&self, "killing contr. client");
-                msg.respond(format!("{}", self.controller.get_target()))
-                    .map_err(|err| {
-                        PubSubError::Client(format!("could not respond: '{}'.", err.to_string()))
-                    })?;
+                let status: PubSubMsg = ControllerPubMsg::Status(self.status()).into();
+                msg.respond(status.to_string()).map_err(|err| {
+                    PubSubError::Client(format!("could not respond: '{}'.", err.to_string()))
+                })?;
                 state = State::Inactive;
             }
 
+            for msg in leases.iter().flat_map(|sub| sub.try_iter()) {
+                match decode_nats_data(&msg.data) {
+                    Ok(lease) => self.update_lease(lease),
+                    Err(err) => log_error(&self, &err.to_string()),
+                }
+            }
+
+            let mut target_changed = false;
             if let Some(msg) = controller.try_next() {
                 // TODO: Match and log error
                 match ControllerSubMsg::try_from(msg) {
@@ -97,25 +322,111 @@ impl PubSubClient for ControllerClient {
                                     new_target, self.id
                                 ),
                             );
-                            self.controller.set_target(new_target)
+                            self.controller.set_target(new_target);
+                            target_changed = true;
                         }
                     },
                     Err(err) => log_error(&self, &err.to_string()),
                 };
             }
+            if let Some(msg) = override_cmd.try_next() {
+                match decode_nats_data(&msg.data) {
+                    Ok(override_) => {
+                        if self.start_override(override_) {
+                            target_changed = true;
+                        }
+                    }
+                    Err(err) => log_error(&self, &err.to_string()),
+                }
+            }
 
-            self.status_update();
+            // Collect measurements until the next update, keeping only the latest per sensor.
+            next_update = next_update.add_secs(self.update_period.as_secs_f32());
+            loop {
+                let remaining = next_update.secs_since(self.clock.now());
+                if remaining <= 0.0 {
+                    break;
+                }
+                match sensor.next_timeout(Duration::from_secs_f32(remaining)) {
+                    Ok(msg) => {
+                        if let Ok(msg) = SensorMsg::try_from(msg) {
+                            self.fusion.update(msg);
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            // Do not try to catch up on missed updates after a long stall.
+            next_update = next_update.max(self.clock.now());
+
+            let fused = self.fusion.fused();
+            self.contributing_sensors = fused
+                .as_ref()
+                .map(|fused| fused.sensors.clone())
+                .unwrap_or_default();
+            let now = self.clock.now();
+            if self.end_override(now) {
+                self.controller.resume();
+                target_changed = true;
+            }
+            let (measurement, meas_time) = match fused {
+                Some(fused) => (Some(fused.value), fused.timestamp),
+                None => (None, now),
+            };
+            // Only measurements newer than the previous update count as fresh.
+            let fresh = measurement.is_some()
+                && previous_meas_time.map_or(true, |previous| meas_time > previous);
+            if fresh {
+                previous_meas_time = Some(meas_time);
+                last_fresh_meas = now;
+                if stale {
+                    stale = false;
+                    self.controller.resume();
+                    self.alarm(
+                        false,
+                        format!("Fresh measurements from {}, resuming", self.sensor_list()),
+                    )?;
+                }
+            } else if !stale && now.secs_since(last_fresh_meas) >= self.stale_timeout.as_secs_f32()
+            {
+                stale = true;
+                self.alarm(
+                    true,
+                    format!(
+                        "No valid measurement from {} in {} ms, output set to safe state",
+                        self.sensor_list(),
+                        self.stale_timeout.as_millis()
+                    ),
+                )?;
+            }
 
-            if let Some(meas_msg) = sensor.next() {
-                if let Ok(msg) = SensorMsg::try_from(meas_msg) {
-                    self.controller.calculate_signal(msg.meas.ok());
+            // Sensor failure takes precedence over an override, for safety.
+            if stale {
+                self.publish_safe_signal()?;
+            } else if self.override_.is_some() {
+                // The controller is not updated, so that it resumes from where it was.
+                self.publish_signal()?;
+            } else {
+                self.controller.calculate_signal(measurement, meas_time);
+                self.publish_signal()?;
+
+                if !tuning_published {
+                    if let Some(result) = self.controller.tuning_result() {
+                        log_info(&self, &format!("auto-tuning finished: {:?}", result));
+                        let msg = ControllerPubMsg::TuningResult {
+                            id: self.id.clone(),
+                            timestamp: self.clock.now(),
+                            result,
+                        };
+                        self.publish(&msg.subject(&self.id), &msg.into())?;
+                        tuning_published = true;
+                    }
                 }
-                let msg = ControllerPubMsg::SetSignal(SignalMsg {
-                    id: self.actor_id.clone(),
-                    timestamp: TimeStamp::now(),
-                    signal: self.controller.get_control_signal(),
-                });
-                self.publish(&msg.subject(&self.actor_id), &msg.into())?;
+            }
+
+            if target_changed || now.secs_since(last_status) >= self.status_period.as_secs_f32() {
+                self.status_update();
+                last_status = now;
             }
         }
         Ok(())
@@ -164,6 +475,34 @@ impl ControllerSubMsg {
     pub fn subject(id: &ClientId) -> Subject {
         Subject(format!("controller.{}.set_target", id))
     }
+
+    pub fn override_subject(id: &ClientId) -> Subject {
+        Subject(format!("controller.{}.override", id))
+    }
+}
+
+/// Manual signal for the output of a controller, for a limited time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ControllerOverride {
+    pub(crate) controller_id: ClientId,
+    pub(crate) signal: f32,
+    pub(crate) duration_ms: u64,
+}
+
+impl Into<PubSubMsg> for ControllerOverride {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
+
+impl Into<PubSubMsg> for ControllerSubMsg {
+    fn into(self) -> PubSubMsg {
+        match self {
+            ControllerSubMsg::SetTarget(new_target) => {
+                PubSubMsg(serde_json::to_string(&new_target).expect("Pub sub serialization error"))
+            }
+        }
+    }
 }
 
 impl TryFrom<Message> for ControllerSubMsg {
@@ -179,15 +518,38 @@ pub enum ControllerPubMsg {
     #[serde(rename = "set_signal")]
     SetSignal(SignalMsg),
     #[serde(rename = "status")]
-    Status {
+    Status(ControllerStatus),
+    #[serde(rename = "tuning_result")]
+    TuningResult {
+        id: ClientId,
+        timestamp: TimeStamp,
+        result: TuningResult,
+    },
+    #[serde(rename = "alarm")]
+    Alarm {
         id: ClientId,
         timestamp: TimeStamp,
-        target: f32,
-        #[serde(rename = "type")]
-        type_: ControllerType,
+        active: bool,
+        msg: String,
     },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ControllerStatus {
+    pub(crate) id: ClientId,
+    pub(crate) timestamp: TimeStamp,
+    pub(crate) target: f32,
+    pub(crate) effective_target: f32,
+    pub(crate) signal: f32,
+    /// Sensors used for the latest measurement.
+    pub(crate) sensors: Vec<ClientId>,
+    #[serde(rename = "type")]
+    pub(crate) type_: ControllerType,
+    /// Time left of a manual override, during which `signal` is the override signal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) override_remaining_ms: Option<u64>,
+}
+
 impl ControllerPubMsg {
     pub fn subject(&self, msg_id: &ClientId) -> Subject {
         match self {
@@ -195,13 +557,31 @@ impl ControllerPubMsg {
                 id: _,
                 timestamp: _,
                 signal: _,
+                moving: _,
+                unknown: _,
+                source: _,
             }) => Subject(format!("actor.{}.set_signal", msg_id)),
-            ControllerPubMsg::Status {
+            ControllerPubMsg::Status(ControllerStatus {
                 id,
                 timestamp: _,
                 target: _,
+                effective_target: _,
+                signal: _,
+                sensors: _,
                 type_: _,
-            } => Subject(format!("controller.{}.status", id)),
+                override_remaining_ms: _,
+            }) => Subject(format!("controller.{}.status", id)),
+            ControllerPubMsg::TuningResult {
+                id,
+                timestamp: _,
+                result: _,
+            } => Subject(format!("controller.{}.tuning_result", id)),
+            ControllerPubMsg::Alarm {
+                id,
+                timestamp: _,
+                active: _,
+                msg: _,
+            } => Subject(format!("controller.{}.alarm", id)),
         }
     }
 }
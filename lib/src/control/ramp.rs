@@ -0,0 +1,138 @@
+use crate::control;
+use crate::control::autotune::TuningResult;
+use crate::time::TimeStamp;
+use std::f32;
+
+/// Setpoint ramping for any controller
+///
+/// A new target does not take effect at once, instead the effective target of the wrapped
+/// controller moves towards it with at most `rate` °C/min.
+pub struct Controller {
+    inner: Box<dyn control::Control>,
+    target: f32,
+    rate: f32,
+    /// Timestamp of the previous calculation, from which the target is ramped.
+    previous_time: Option<TimeStamp>,
+}
+
+impl Controller {
+    pub fn try_new(
+        inner: Box<dyn control::Control>,
+        rate: f32,
+    ) -> Result<Controller, control::ControllerError> {
+        if rate > 0.0 {
+            Ok(Controller {
+                target: inner.get_target(),
+                inner,
+                rate,
+                previous_time: None,
+            })
+        } else {
+            Err(control::ControllerError::ParamError(format!(
+                "Ramp rate must be positive ({} !> 0.0)",
+                rate
+            )))
+        }
+    }
+
+    /// Move the effective target `dt` seconds towards the requested target.
+    fn ramp(&mut self, dt: f32) {
+        let effective_target = self.inner.get_target();
+        let max_change = self.rate / 60.0 * dt;
+        let diff = self.target - effective_target;
+        if diff.abs() <= max_change {
+            self.inner.set_target(self.target);
+        } else {
+            self.inner
+                .set_target(effective_target + max_change.copysign(diff));
+        }
+    }
+}
+
+impl control::Control for Controller {
+    fn calculate_signal(&mut self, measurement: Option<f32>, timestamp: TimeStamp) -> f32 {
+        // Measurement timestamps may be older than the time of a call without a measurement.
+        if let Some(previous_time) = self.previous_time {
+            self.ramp(timestamp.secs_since(previous_time).max(0.0));
+        }
+        self.previous_time = Some(
+            self.previous_time
+                .map_or(timestamp, |previous_time| previous_time.max(timestamp)),
+        );
+        self.inner.calculate_signal(measurement, timestamp)
+    }
+
+    fn get_state(&self) -> control::State {
+        self.inner.get_state()
+    }
+
+    fn set_state(&mut self, new_state: control::State) {
+        self.inner.set_state(new_state);
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.inner.get_control_signal()
+    }
+
+    fn get_target(&self) -> f32 {
+        self.target
+    }
+
+    fn get_effective_target(&self) -> f32 {
+        self.inner.get_target()
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        self.target = new_target;
+    }
+
+    fn transfer_from(&mut self, signal: f32) {
+        self.inner.transfer_from(signal);
+    }
+
+    fn resume(&mut self) {
+        self.inner.resume();
+    }
+
+    fn tuning_result(&self) -> Option<TuningResult> {
+        self.inner.tuning_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::{manual, Control};
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_constructor_non_positive_rate() {
+        let controller = Controller::try_new(Box::new(manual::Controller::new(50.0)), 0.0);
+        assert!(controller.is_err())
+    }
+
+    #[test]
+    fn test_ramp_up_and_down() {
+        let mut controller =
+            Controller::try_new(Box::new(manual::Controller::new(50.0)), 1.0).unwrap();
+        controller.set_target(53.0);
+        assert_approx_eq!(controller.get_target(), 53.0);
+        assert_approx_eq!(controller.get_effective_target(), 50.0);
+        controller.ramp(60.0);
+        assert_approx_eq!(controller.get_effective_target(), 51.0);
+        controller.ramp(600.0);
+        assert_approx_eq!(controller.get_effective_target(), 53.0);
+        controller.set_target(52.5);
+        controller.ramp(60.0);
+        assert_approx_eq!(controller.get_effective_target(), 52.5);
+    }
+
+    #[test]
+    fn test_inner_controller_uses_effective_target() {
+        let mut controller =
+            Controller::try_new(Box::new(manual::Controller::new(0.0)), 30.0).unwrap();
+        controller.set_target(1.0);
+        assert_approx_eq!(controller.calculate_signal(None, TimeStamp(0)), 0.0);
+        assert_approx_eq!(controller.calculate_signal(None, TimeStamp(1000)), 0.5);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::control;
+use crate::time::TimeStamp;
+
+pub(crate) const HEAT: f32 = 1.0;
+pub(crate) const IDLE: f32 = 0.0;
+pub(crate) const COOL: f32 = -1.0;
+
+/// Split-range heating and cooling
+///
+/// The signal is `1.0` for heating, `-1.0` for cooling and `0.0` for idle.
+/// Heating starts when the measurement is more than half the deadband below the target,
+/// cooling when it is more than half the deadband above it. Both stop at the target.
+/// Min. on and off times of a compressor are kept by its actor, see `ActorConfig`,
+/// so that they also hold across overrides and controller switches.
+pub struct Controller {
+    target: f32,
+    current_signal: f32,
+    state: control::State,
+    deadband: f32,
+}
+
+impl Controller {
+    pub fn try_new(target: f32, deadband: f32) -> Result<Controller, control::ControllerError> {
+        if deadband >= 0.0 {
+            Ok(Controller {
+                target,
+                current_signal: IDLE,
+                state: control::State::Active,
+                deadband,
+            })
+        } else {
+            Err(control::ControllerError::ParamError(format!(
+                "deadband must be non-negative ({} !>= 0.0)",
+                deadband
+            )))
+        }
+    }
+
+    fn update(&mut self, measurement: f32) -> f32 {
+        let diff = measurement - self.target;
+        if (self.current_signal == HEAT && diff >= 0.0)
+            || (self.current_signal == COOL && diff <= 0.0)
+        {
+            self.current_signal = IDLE;
+        }
+
+        if self.current_signal == IDLE {
+            if diff < -self.deadband / 2.0 {
+                self.current_signal = HEAT;
+            } else if diff > self.deadband / 2.0 {
+                self.current_signal = COOL;
+            }
+        }
+        self.current_signal
+    }
+}
+
+impl control::Control for Controller {
+    fn calculate_signal(&mut self, measurement: Option<f32>, _timestamp: TimeStamp) -> f32 {
+        match measurement {
+            Some(measurement) => self.update(measurement),
+            None => self.current_signal,
+        }
+    }
+
+    fn get_state(&self) -> control::State {
+        self.state
+    }
+
+    fn set_state(&mut self, new_state: control::State) {
+        self.state = new_state;
+    }
+
+    fn set_target(&mut self, new_target: f32) {
+        self.target = new_target;
+    }
+
+    fn get_target(&self) -> f32 {
+        self.target
+    }
+
+    fn get_control_signal(&self) -> f32 {
+        self.current_signal
+    }
+
+    fn transfer_from(&mut self, signal: f32) {
+        self.current_signal = if signal > 0.0 {
+            HEAT
+        } else if signal < 0.0 {
+            COOL
+        } else {
+            IDLE
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constructor_neg_deadband() {
+        let controller = Controller::try_new(18.0, -1.0);
+        assert!(controller.is_err())
+    }
+
+    #[test]
+    fn test_deadband() {
+        let mut controller = Controller::try_new(18.0, 1.0).unwrap();
+        assert_eq!(controller.update(17.6), IDLE);
+        assert_eq!(controller.update(17.4), HEAT);
+        assert_eq!(controller.update(17.9), HEAT);
+        assert_eq!(controller.update(18.0), IDLE);
+        assert_eq!(controller.update(18.4), IDLE);
+        assert_eq!(controller.update(18.6), COOL);
+        assert_eq!(controller.update(18.1), COOL);
+        assert_eq!(controller.update(18.0), IDLE);
+        // Straight from cooling to heating.
+        assert_eq!(controller.update(18.6), COOL);
+        assert_eq!(controller.update(17.0), HEAT);
+    }
+}
@@ -0,0 +1,324 @@
+//! Fermentation temperature profiles
+//!
+//! A profile is a list of steps spanning several days, which sets the target of a controller
+//! started together with the program. Progress only depends on the wall-clock start time,
+//! which is persisted so that a program is resumed when the supervisor restarts.
+use crate::control::ControllerConfig;
+use crate::pub_sub::ClientId;
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub mod pub_sub;
+pub use pub_sub::FermentationClient;
+
+const SECS_PER_HOUR: f32 = 3600.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProfileStep {
+    /// Keep `target` for `hours`.
+    #[serde(rename = "hold")]
+    Hold { target: f32, hours: f32 },
+    /// Linear change from the previous target to `target` over `hours`.
+    #[serde(rename = "ramp")]
+    Ramp { target: f32, hours: f32 },
+}
+
+impl ProfileStep {
+    fn target(&self) -> f32 {
+        match self {
+            ProfileStep::Hold { target, .. } | ProfileStep::Ramp { target, .. } => *target,
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        match self {
+            ProfileStep::Hold { hours, .. } | ProfileStep::Ramp { hours, .. } => {
+                hours * SECS_PER_HOUR
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FermentationConfig {
+    pub(crate) program_id: ClientId,
+    /// Controller started and driven by the program.
+    pub(crate) controller: ControllerConfig,
+    pub(crate) steps: Vec<ProfileStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    #[serde(rename = "ramping")]
+    Ramping,
+    #[serde(rename = "holding")]
+    Holding,
+    #[serde(rename = "done")]
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfilePoint {
+    pub step: usize,
+    pub phase: Phase,
+    pub target: f32,
+    /// Remaining time of the current step in seconds.
+    pub step_remaining: f32,
+    /// Remaining time of the whole profile in seconds.
+    pub remaining: f32,
+}
+
+pub struct Profile {
+    steps: Vec<ProfileStep>,
+}
+
+impl Profile {
+    pub fn try_new(steps: Vec<ProfileStep>) -> Result<Profile, FermentationError> {
+        if steps.is_empty() {
+            return Err(FermentationError::Config(String::from(
+                "A profile needs at least one step",
+            )));
+        }
+        for step in &steps {
+            match step {
+                ProfileStep::Hold { hours, .. } if *hours < 0.0 => {
+                    return Err(FermentationError::Config(format!(
+                        "Hold duration must be non-negative ({} !>= 0.0)",
+                        hours
+                    )))
+                }
+                ProfileStep::Ramp { hours, .. } if *hours <= 0.0 => {
+                    return Err(FermentationError::Config(format!(
+                        "Ramp duration must be positive ({} !> 0.0)",
+                        hours
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(Profile { steps })
+    }
+
+    pub fn num_steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    fn total_duration(&self) -> f32 {
+        self.steps.iter().map(ProfileStep::duration).sum()
+    }
+
+    /// Profile state `elapsed` seconds after the start.
+    ///
+    /// A ramp as the first step starts from its own target.
+    pub fn at(&self, elapsed: f32) -> ProfilePoint {
+        let remaining = (self.total_duration() - elapsed).max(0.0);
+        let mut step_start = 0.0;
+        let mut previous_target = self.steps[0].target();
+        for (idx, step) in self.steps.iter().enumerate() {
+            let step_end = step_start + step.duration();
+            if elapsed < step_end {
+                let (phase, target) = match step {
+                    ProfileStep::Hold { target, .. } => (Phase::Holding, *target),
+                    ProfileStep::Ramp { target, .. } => {
+                        let fraction = (elapsed - step_start).max(0.0) / step.duration();
+                        (
+                            Phase::Ramping,
+                            previous_target + fraction * (target - previous_target),
+                        )
+                    }
+                };
+                return ProfilePoint {
+                    step: idx,
+                    phase,
+                    target,
+                    step_remaining: step_end - elapsed,
+                    remaining,
+                };
+            }
+            step_start = step_end;
+            previous_target = step.target();
+        }
+        ProfilePoint {
+            step: self.steps.len(),
+            phase: Phase::Done,
+            target: previous_target,
+            step_remaining: 0.0,
+            remaining,
+        }
+    }
+}
+
+/// Seconds from `started` to `now`.
+pub fn elapsed(started: TimeStamp, now: TimeStamp) -> f32 {
+    now.0.saturating_sub(started.0) as f32 / 1000.0
+}
+
+/// Persisted progress of a running program.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FermentationState {
+    pub(crate) config: FermentationConfig,
+    pub(crate) started: TimeStamp,
+}
+
+impl FermentationState {
+    pub fn new(config: FermentationConfig, started: TimeStamp) -> Self {
+        FermentationState { config, started }
+    }
+
+    fn path(dir: &Path, id: &ClientId) -> PathBuf {
+        dir.join(format!("{}.json", id))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), FermentationError> {
+        fs::create_dir_all(dir)?;
+        let path = FermentationState::path(dir, &self.config.program_id);
+        // Write to a temporary file first, so that a crash never leaves a truncated state.
+        let tmp_path = path.with_extension("json.tmp");
+        let state = serde_json::to_string_pretty(self)
+            .map_err(|err| FermentationError::Parse(err.to_string()))?;
+        fs::write(&tmp_path, state)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn remove(dir: &Path, id: &ClientId) -> Result<(), FermentationError> {
+        let path = FermentationState::path(dir, id);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Load the states of all programs in `dir`, each on its own.
+    ///
+    /// A state which cannot be read is moved aside to `<id>.json.invalid` and returned as
+    /// an error, so that it is kept for inspection but not loaded again.
+    pub fn load_all(
+        dir: &Path,
+    ) -> Result<Vec<Result<FermentationState, FermentationError>>, FermentationError> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut states = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                let state = FermentationState::load(&path).or_else(|err| {
+                    fs::rename(&path, path.with_extension("json.invalid"))?;
+                    Err(err)
+                });
+                states.push(state);
+            }
+        }
+        Ok(states)
+    }
+
+    fn load(path: &Path) -> Result<FermentationState, FermentationError> {
+        let state = fs::read_to_string(path)?;
+        serde_json::from_str(&state)
+            .map_err(|err| FermentationError::Parse(format!("{}: {}", path.display(), err)))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FermentationError {
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Parse error: {0}")]
+    Parse(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const DAY: f32 = 24.0 * SECS_PER_HOUR;
+
+    fn profile() -> Profile {
+        Profile::try_new(vec![
+            ProfileStep::Hold {
+                target: 18.0,
+                hours: 5.0 * 24.0,
+            },
+            ProfileStep::Ramp {
+                target: 21.0,
+                hours: 2.0 * 24.0,
+            },
+            ProfileStep::Hold {
+                target: 2.0,
+                hours: 3.0 * 24.0,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_invalid_profile() {
+        assert!(Profile::try_new(Vec::new()).is_err());
+        assert!(Profile::try_new(vec![ProfileStep::Ramp {
+            target: 20.0,
+            hours: 0.0
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let profile = profile();
+        let point = profile.at(DAY);
+        assert_eq!(point.phase, Phase::Holding);
+        assert_approx_eq!(point.target, 18.0);
+        assert_approx_eq!(point.step_remaining, 4.0 * DAY);
+        assert_approx_eq!(point.remaining, 9.0 * DAY);
+
+        let point = profile.at(6.0 * DAY);
+        assert_eq!(point.step, 1);
+        assert_eq!(point.phase, Phase::Ramping);
+        assert_approx_eq!(point.target, 19.5);
+
+        let point = profile.at(7.5 * DAY);
+        assert_eq!(point.step, 2);
+        assert_approx_eq!(point.target, 2.0);
+
+        let point = profile.at(11.0 * DAY);
+        assert_eq!(point.phase, Phase::Done);
+        assert_approx_eq!(point.target, 2.0);
+        assert_approx_eq!(point.remaining, 0.0);
+    }
+
+    #[test]
+    fn test_persist_state() {
+        let dir = std::env::temp_dir().join(format!("bryggio-ferm-{}", std::process::id()));
+        let state = FermentationState::new(
+            FermentationConfig {
+                program_id: ClientId("ale".into()),
+                controller: ControllerConfig::dummy(),
+                steps: vec![ProfileStep::Hold {
+                    target: 18.0,
+                    hours: 1.0,
+                }],
+            },
+            TimeStamp(1000),
+        );
+        state.save(&dir).unwrap();
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        let loaded = FermentationState::load_all(&dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let loaded: Vec<_> = loaded.into_iter().filter_map(Result::ok).collect();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].started, TimeStamp(1000));
+        assert_approx_eq!(elapsed(loaded[0].started, TimeStamp(61_000)), 60.0);
+        // The broken state is kept, but not loaded again.
+        assert!(dir.join("broken.json.invalid").exists());
+
+        FermentationState::remove(&dir, &ClientId("ale".into())).unwrap();
+        assert!(FermentationState::load_all(&dir).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
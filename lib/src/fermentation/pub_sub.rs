@@ -0,0 +1,171 @@
+use crate::control::pub_sub::ControllerSubMsg;
+use crate::fermentation::{
+    elapsed, FermentationConfig, FermentationError, FermentationState, Phase, Profile,
+};
+use crate::logger::info;
+use crate::pub_sub::{
+    nats_client::NatsClient, nats_client::NatsConfig, ClientId, ClientState, PubSubClient,
+    PubSubError, PubSubMsg, Subject,
+};
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::TimeStamp;
+use nats::Subscription;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const UPDATE_PERIOD: Duration = Duration::from_millis(1000);
+/// Resolution of the targets sent to the controller, so that a ramp is not sent every update.
+const TARGET_RESOLUTION: f32 = 0.1;
+
+pub struct FermentationClient {
+    id: ClientId,
+    controller_id: ClientId,
+    profile: Profile,
+    started: TimeStamp,
+    client: NatsClient,
+}
+
+impl FermentationClient {
+    pub fn try_new(
+        state: &FermentationState,
+        nats_config: &NatsConfig,
+    ) -> Result<Self, FermentationError> {
+        let FermentationConfig {
+            program_id,
+            controller,
+            steps,
+        } = state.config.clone();
+        let client = NatsClient::try_new(nats_config).unwrap();
+        Ok(FermentationClient {
+            id: program_id,
+            controller_id: controller.controller_id,
+            profile: Profile::try_new(steps)?,
+            started: state.started,
+            client,
+        })
+    }
+
+    fn status(&self) -> FermentationStatus {
+        let now = TimeStamp::now();
+        let point = self.profile.at(elapsed(self.started, now));
+        FermentationStatus {
+            id: self.id.clone(),
+            timestamp: now,
+            started: self.started,
+            step: point.step,
+            num_steps: self.profile.num_steps(),
+            phase: point.phase,
+            target: point.target,
+            step_remaining: point.step_remaining,
+            eta: TimeStamp(now.0 + (point.remaining * 1000.0) as u128),
+        }
+    }
+}
+
+impl PubSubClient for FermentationClient {
+    fn client_loop(self) -> Result<(), PubSubError> {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        info(
+            &self,
+            format!(
+                "Running fermentation program '{}' on controller '{}'",
+                self.id, self.controller_id
+            ),
+            &format!("fermentation.{}", self.id),
+        );
+        let mut state = ClientState::Active;
+        let mut last_target = None;
+        let mut last_step = None;
+        while state == ClientState::Active {
+            let status = self.status();
+            let target = (status.target / TARGET_RESOLUTION).round() * TARGET_RESOLUTION;
+            if last_target != Some(target) {
+                let msg = ControllerSubMsg::SetTarget(target);
+                self.publish(&ControllerSubMsg::subject(&self.controller_id), &msg.into())?;
+                last_target = Some(target);
+            }
+            if last_step != Some(status.step) {
+                info(
+                    &self,
+                    format!(
+                        "Step {}/{}: {:?}",
+                        status.step + 1,
+                        status.num_steps,
+                        status.phase
+                    ),
+                    &format!("fermentation.{}", self.id),
+                );
+                last_step = Some(status.step);
+            }
+            let msg = FermentationPubMsg::Status(status);
+            self.publish(&msg.subject(), &msg.into())?;
+
+            // The kill command doubles as the timer of the loop.
+            if let Ok(msg) = kill_cmd.next_timeout(UPDATE_PERIOD) {
+                info(
+                    &self,
+                    String::from("Stopping fermentation program"),
+                    &format!("fermentation.{}", self.id),
+                );
+                let status: PubSubMsg = FermentationPubMsg::Status(self.status()).into();
+                msg.respond(status.to_string())
+                    .map_err(|err| PubSubError::Reply {
+                        msg: msg.to_string(),
+                        err: err.to_string(),
+                    })?;
+                state = ClientState::Inactive;
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        self.client.subscribe(subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        self.client.publish(subject, msg)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FermentationPubMsg {
+    #[serde(rename = "status")]
+    Status(FermentationStatus),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FermentationStatus {
+    pub(crate) id: ClientId,
+    pub(crate) timestamp: TimeStamp,
+    pub(crate) started: TimeStamp,
+    pub(crate) step: usize,
+    pub(crate) num_steps: usize,
+    pub(crate) phase: Phase,
+    pub(crate) target: f32,
+    /// Remaining time of the current step in seconds.
+    pub(crate) step_remaining: f32,
+    /// Estimated end of the profile.
+    pub(crate) eta: TimeStamp,
+}
+
+impl FermentationPubMsg {
+    pub fn subject(&self) -> Subject {
+        match self {
+            FermentationPubMsg::Status(status) => {
+                Subject(format!("fermentation.{}.status", status.id))
+            }
+        }
+    }
+}
+
+impl Into<PubSubMsg> for FermentationPubMsg {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
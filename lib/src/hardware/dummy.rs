@@ -1,10 +1,5 @@
 use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use embedded_hal::digital::{InputPin, OutputPin};
-use gpio_cdev::errors;
-
-pub fn get_gpio_pin(pin_number: u32, label: &str) -> Result<GpioPin, errors::Error> {
-    Ok(GpioPin::new(pin_number, label))
-}
 
 pub struct GpioPin {
     pub pin_number: u32,
@@ -13,11 +8,15 @@ pub struct GpioPin {
 }
 
 impl GpioPin {
-    pub fn new(pin_number: u32, label: &str) -> Self {
+    pub fn new(pin_number: u32, label: &str, high: bool) -> Self {
         GpioPin {
             pin_number,
             label: label.into(),
-            state: GpioState::Low,
+            state: if high {
+                GpioState::High
+            } else {
+                GpioState::Low
+            },
         }
     }
 }
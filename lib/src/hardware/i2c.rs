@@ -0,0 +1,112 @@
+use crate::hardware::HardwareError;
+use embedded_hal::blocking::i2c::{SevenBitAddress, Write, WriteRead};
+use i2cdev::core::{I2CMessage, I2CTransfer};
+use i2cdev::linux::{LinuxI2CBus, LinuxI2CMessage};
+use std::collections::HashMap;
+
+/// Size of the register file of a dummy device.
+const DUMMY_REGISTERS: usize = 256;
+
+pub enum I2cBus {
+    Linux(LinuxI2CBus),
+    Dummy(DummyI2c),
+}
+
+impl Write for I2cBus {
+    type Error = HardwareError;
+
+    fn try_write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            I2cBus::Linux(bus) => {
+                let mut msgs = [LinuxI2CMessage::write(bytes).with_address(address.into())];
+                bus.transfer(&mut msgs)?;
+                Ok(())
+            }
+            I2cBus::Dummy(bus) => Ok(bus.try_write(address, bytes)?),
+        }
+    }
+}
+
+impl WriteRead for I2cBus {
+    type Error = HardwareError;
+
+    fn try_write_read(
+        &mut self,
+        address: SevenBitAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        match self {
+            I2cBus::Linux(bus) => {
+                let mut msgs = [
+                    LinuxI2CMessage::write(bytes).with_address(address.into()),
+                    LinuxI2CMessage::read(buffer).with_address(address.into()),
+                ];
+                bus.transfer(&mut msgs)?;
+                Ok(())
+            }
+            I2cBus::Dummy(bus) => Ok(bus.try_write_read(address, bytes, buffer)?),
+        }
+    }
+}
+
+/// In-memory bus of register based devices.
+///
+/// The first byte written selects a register and the following ones are written to it
+/// and the registers after it, which is how most I2C devices are addressed.
+#[derive(Default)]
+pub struct DummyI2c {
+    devices: HashMap<SevenBitAddress, Vec<u8>>,
+}
+
+impl DummyI2c {
+    pub fn register(&self, address: SevenBitAddress, register: u8) -> u8 {
+        self.devices
+            .get(&address)
+            .map_or(0, |registers| registers[usize::from(register)])
+    }
+
+    pub fn set_register(&mut self, address: SevenBitAddress, register: u8, value: u8) {
+        self.device(address)[usize::from(register)] = value;
+    }
+
+    fn device(&mut self, address: SevenBitAddress) -> &mut Vec<u8> {
+        self.devices
+            .entry(address)
+            .or_insert_with(|| vec![0; DUMMY_REGISTERS])
+    }
+}
+
+impl Write for DummyI2c {
+    type Error = HardwareError;
+
+    fn try_write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        if let Some((register, values)) = bytes.split_first() {
+            let registers = self.device(address);
+            for (offset, value) in values.iter().enumerate() {
+                registers[(usize::from(*register) + offset) % DUMMY_REGISTERS] = *value;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for DummyI2c {
+    type Error = HardwareError;
+
+    fn try_write_read(
+        &mut self,
+        address: SevenBitAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let register = bytes
+            .first()
+            .copied()
+            .ok_or_else(|| HardwareError::I2c(String::from("No register selected before read")))?;
+        for (offset, value) in buffer.iter_mut().enumerate() {
+            *value = self.register(address, register.wrapping_add(offset as u8));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! MCP23017 16-bit I/O expander, e.g. on I2C relay boards
+//!
+//! Pins 0-7 are port A and pins 8-15 port B. The registers are addressed with the
+//! power-on default `IOCON.BANK = 0`.
+use crate::hardware::HardwareError;
+use embedded_hal::blocking::i2c::{SevenBitAddress, Write, WriteRead};
+use embedded_hal::digital::OutputPin;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+pub const NUM_PINS: u8 = 16;
+
+const IODIRA: u8 = 0x00;
+const OLATA: u8 = 0x14;
+
+/// Drive the `outputs` of the expander to their safe levels in `latch`, one bit per pin,
+/// and make all other pins inputs.
+///
+/// The latch is written before the directions, so that an output never drives anything but
+/// its safe level. Pins left as outputs by a previous run are released this way.
+pub fn reset<I2C, E>(
+    bus: &Mutex<I2C>,
+    address: SevenBitAddress,
+    outputs: u16,
+    latch: u16,
+) -> Result<(), HardwareError>
+where
+    I2C: Write<Error = E>,
+    E: Debug,
+{
+    let mut bus = bus.lock().map_err(|_| poisoned(address))?;
+    let [latch_a, latch_b] = latch.to_le_bytes();
+    bus.try_write(address, &[OLATA, latch_a, latch_b])
+        .map_err(|err| i2c_error(address, err))?;
+    let [dir_a, dir_b] = (!outputs).to_le_bytes();
+    bus.try_write(address, &[IODIRA, dir_a, dir_b])
+        .map_err(|err| i2c_error(address, err))
+}
+
+/// Single output pin of an expander, with the I2C bus shared with the other pins.
+///
+/// The output latch is written before the pin is switched to an output, so that the pin
+/// never drives anything but the first level set.
+pub struct Mcp23017Pin<I2C> {
+    bus: Arc<Mutex<I2C>>,
+    address: SevenBitAddress,
+    pin: u8,
+    is_output: bool,
+}
+
+impl<I2C, E> Mcp23017Pin<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    pub fn try_new(
+        bus: Arc<Mutex<I2C>>,
+        address: SevenBitAddress,
+        pin: u8,
+    ) -> Result<Self, HardwareError> {
+        if pin >= NUM_PINS {
+            return Err(HardwareError::I2c(format!(
+                "MCP23017 at {:#04x} has no pin {}",
+                address, pin
+            )));
+        }
+        Ok(Mcp23017Pin {
+            bus,
+            address,
+            pin,
+            is_output: false,
+        })
+    }
+
+    fn set(&mut self, high: bool) -> Result<(), HardwareError> {
+        let port = self.pin / 8;
+        let mask = 1 << (self.pin % 8);
+        let mut bus = self.bus.lock().map_err(|_| poisoned(self.address))?;
+        // The read-modify-writes are done under the bus lock, so that pins of the same
+        // expander do not overwrite each other.
+        update_register(&mut *bus, self.address, OLATA + port, |latch| {
+            if high {
+                latch | mask
+            } else {
+                latch & !mask
+            }
+        })?;
+        if !self.is_output {
+            update_register(&mut *bus, self.address, IODIRA + port, |dir| dir & !mask)?;
+            self.is_output = true;
+        }
+        Ok(())
+    }
+}
+
+fn update_register<I2C, E>(
+    bus: &mut I2C,
+    address: SevenBitAddress,
+    register: u8,
+    update: impl FnOnce(u8) -> u8,
+) -> Result<(), HardwareError>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    let mut value = [0];
+    bus.try_write_read(address, &[register], &mut value)
+        .map_err(|err| i2c_error(address, err))?;
+    bus.try_write(address, &[register, update(value[0])])
+        .map_err(|err| i2c_error(address, err))
+}
+
+fn i2c_error(address: SevenBitAddress, err: impl Debug) -> HardwareError {
+    HardwareError::I2c(format!("MCP23017 at {:#04x}: {:?}", address, err))
+}
+
+fn poisoned(address: SevenBitAddress) -> HardwareError {
+    HardwareError::I2c(format!("Bus of MCP23017 at {:#04x} is poisoned", address))
+}
+
+impl<I2C, E> OutputPin for Mcp23017Pin<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: Debug,
+{
+    type Error = HardwareError;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false)
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_0_2::blocking::i2c as i2c_0_2;
+    use embedded_hal_mock::i2c::{Mock as I2cMock, Transaction};
+    use embedded_hal_mock::MockError;
+
+    const ADDRESS: SevenBitAddress = 0x20;
+    const IODIRB: u8 = 0x01;
+    const OLATB: u8 = 0x15;
+
+    /// `embedded-hal-mock` implements the embedded-hal 0.2 traits, so its bus is adapted here.
+    struct MockBus(I2cMock);
+
+    impl Write for MockBus {
+        type Error = MockError;
+
+        fn try_write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+            i2c_0_2::Write::write(&mut self.0, address, bytes)
+        }
+    }
+
+    impl WriteRead for MockBus {
+        type Error = MockError;
+
+        fn try_write_read(
+            &mut self,
+            address: SevenBitAddress,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Self::Error> {
+            i2c_0_2::WriteRead::write_read(&mut self.0, address, bytes, buffer)
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut mock = I2cMock::new(&[
+            Transaction::write(ADDRESS, vec![OLATA, 0b0000_0000, 0b0000_0010]),
+            Transaction::write(ADDRESS, vec![IODIRA, 0b1111_1101, 0b1111_1101]),
+        ]);
+        let bus = Mutex::new(MockBus(mock.clone()));
+        reset(&bus, ADDRESS, 0b0000_0010_0000_0010, 0b0000_0010_0000_0000).unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_shared_bus() {
+        let mut mock = I2cMock::new(&[
+            // Pin 1 on, the latch is written before the pin is made an output.
+            Transaction::write_read(ADDRESS, vec![OLATA], vec![0b0000_0000]),
+            Transaction::write(ADDRESS, vec![OLATA, 0b0000_0010]),
+            Transaction::write_read(ADDRESS, vec![IODIRA], vec![0b1111_1111]),
+            Transaction::write(ADDRESS, vec![IODIRA, 0b1111_1101]),
+            // Pin 9 off, with the other pins of port B kept.
+            Transaction::write_read(ADDRESS, vec![OLATB], vec![0b1111_1111]),
+            Transaction::write(ADDRESS, vec![OLATB, 0b1111_1101]),
+            Transaction::write_read(ADDRESS, vec![IODIRB], vec![0b1111_1111]),
+            Transaction::write(ADDRESS, vec![IODIRB, 0b1111_1101]),
+            // Already outputs.
+            Transaction::write_read(ADDRESS, vec![OLATB], vec![0b1111_1101]),
+            Transaction::write(ADDRESS, vec![OLATB, 0b1111_1111]),
+            Transaction::write_read(ADDRESS, vec![OLATA], vec![0b0000_0010]),
+            Transaction::write(ADDRESS, vec![OLATA, 0b0000_0000]),
+        ]);
+        let bus = Arc::new(Mutex::new(MockBus(mock.clone())));
+        let mut pin_a = Mcp23017Pin::try_new(bus.clone(), ADDRESS, 1).unwrap();
+        let mut pin_b = Mcp23017Pin::try_new(bus, ADDRESS, 9).unwrap();
+        pin_a.try_set_high().unwrap();
+        pin_b.try_set_low().unwrap();
+        pin_b.try_set_high().unwrap();
+        pin_a.try_set_low().unwrap();
+        mock.done();
+    }
+
+    #[test]
+    fn test_invalid_pin() {
+        let mut mock = I2cMock::new(&[]);
+        let bus = Arc::new(Mutex::new(MockBus(mock.clone())));
+        assert!(Mcp23017Pin::try_new(bus, ADDRESS, NUM_PINS).is_err());
+        mock.done();
+    }
+
+    #[test]
+    fn test_bus_error() {
+        let mut mock =
+            I2cMock::new(&[
+                Transaction::write_read(ADDRESS, vec![OLATA], vec![0b0000_0000])
+                    .with_error(MockError::Io(std::io::ErrorKind::Other)),
+            ]);
+        let bus = Arc::new(Mutex::new(MockBus(mock.clone())));
+        let mut pin = Mcp23017Pin::try_new(bus, ADDRESS, 0).unwrap();
+        assert!(pin.try_set_high().is_err());
+        mock.done();
+    }
+}
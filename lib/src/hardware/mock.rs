@@ -0,0 +1,40 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Current state of all mock pins, by pin number.
+    static ref PIN_STATES: Mutex<HashMap<u32, bool>> = Mutex::new(HashMap::new());
+}
+
+/// In-memory pin, with a state which can be read with `pin_state`.
+pub struct MockPin {
+    pin_number: u32,
+}
+
+impl MockPin {
+    pub fn new(pin_number: u32) -> Self {
+        MockPin { pin_number }
+    }
+
+    pub fn set(&mut self, high: bool) {
+        PIN_STATES
+            .lock()
+            .expect("Mock pin lock poisoned")
+            .insert(self.pin_number, high);
+    }
+
+    /// Pins which have never been set read as low.
+    pub fn get(&self) -> bool {
+        pin_state(self.pin_number).unwrap_or(false)
+    }
+}
+
+/// State of a mock pin, `None` if it has never been set.
+pub fn pin_state(pin_number: u32) -> Option<bool> {
+    PIN_STATES
+        .lock()
+        .expect("Mock pin lock poisoned")
+        .get(&pin_number)
+        .copied()
+}
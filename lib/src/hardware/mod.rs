@@ -1,4 +1,229 @@
-#[cfg(target_arch = "x86_64")]
-pub(crate) mod dummy;
-#[cfg(target_arch = "arm")]
-pub(crate) mod rbpi;
+//! GPIO and I2C backends, selected at runtime from the supervisor config
+use embedded_hal::blocking::i2c::SevenBitAddress;
+use embedded_hal::digital::{InputPin, OutputPin};
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use i2cdev::linux::{LinuxI2CBus, LinuxI2CError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+pub mod dummy;
+pub mod i2c;
+pub mod mcp23017;
+pub mod mock;
+
+pub type SharedI2cBus = Arc<Mutex<i2c::I2cBus>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GpioBackend {
+    /// Linux GPIO character device, and I2C through `/dev/i2c-<bus>`.
+    #[serde(rename = "cdev")]
+    Cdev { chip: PathBuf },
+    /// In-memory pins and I2C devices, which do nothing.
+    #[serde(rename = "dummy")]
+    Dummy,
+    /// In-memory pins, with states which can be inspected, for tests.
+    #[serde(rename = "mock")]
+    Mock,
+}
+
+impl Default for GpioBackend {
+    fn default() -> Self {
+        GpioBackend::Cdev {
+            chip: PathBuf::from("/dev/gpiochip0"),
+        }
+    }
+}
+
+impl GpioBackend {
+    /// Whether the backend controls real hardware.
+    pub fn is_hardware(&self) -> bool {
+        match self {
+            GpioBackend::Cdev { .. } => true,
+            GpioBackend::Dummy | GpioBackend::Mock => false,
+        }
+    }
+
+    /// Output pin, starting at the physical level `high`, so that it does not glitch on
+    /// before the actor sets it.
+    pub fn get_gpio_pin(
+        &self,
+        pin_number: u32,
+        label: &str,
+        high: bool,
+    ) -> Result<GpioPin, HardwareError> {
+        match self {
+            GpioBackend::Cdev { chip } => {
+                let mut chip = Chip::new(chip)?;
+                let handle = chip.get_line(pin_number)?.request(
+                    LineRequestFlags::OUTPUT,
+                    high.into(),
+                    label,
+                )?;
+                Ok(GpioPin::Cdev(handle))
+            }
+            GpioBackend::Dummy => Ok(GpioPin::Dummy(dummy::GpioPin::new(pin_number, label, high))),
+            GpioBackend::Mock => {
+                let mut pin = mock::MockPin::new(pin_number);
+                pin.set(high);
+                Ok(GpioPin::Mock(pin))
+            }
+        }
+    }
+
+    pub fn get_input_pin(&self, pin_number: u32, label: &str) -> Result<GpioPin, HardwareError> {
+        match self {
+            GpioBackend::Cdev { chip } => {
+                let mut chip = Chip::new(chip)?;
+                let handle =
+                    chip.get_line(pin_number)?
+                        .request(LineRequestFlags::INPUT, 0, label)?;
+                Ok(GpioPin::Cdev(handle))
+            }
+            GpioBackend::Dummy => Ok(GpioPin::Dummy(dummy::GpioPin::new(
+                pin_number, label, false,
+            ))),
+            // Mock inputs are set by the tests.
+            GpioBackend::Mock => Ok(GpioPin::Mock(mock::MockPin::new(pin_number))),
+        }
+    }
+
+    pub fn get_i2c_bus(&self, bus: u32) -> Result<i2c::I2cBus, HardwareError> {
+        match self {
+            GpioBackend::Cdev { .. } => Ok(i2c::I2cBus::Linux(LinuxI2CBus::new(format!(
+                "/dev/i2c-{}",
+                bus
+            ))?)),
+            GpioBackend::Dummy | GpioBackend::Mock => {
+                Ok(i2c::I2cBus::Dummy(i2c::DummyI2c::default()))
+            }
+        }
+    }
+}
+
+/// Hardware shared between actors, e.g. an I2C bus with an expander driving several relays.
+pub struct Devices {
+    gpio: GpioBackend,
+    i2c_buses: HashMap<u32, SharedI2cBus>,
+}
+
+impl Devices {
+    pub fn new(gpio: GpioBackend) -> Self {
+        Devices {
+            gpio,
+            i2c_buses: HashMap::new(),
+        }
+    }
+
+    pub fn gpio(&self) -> &GpioBackend {
+        &self.gpio
+    }
+
+    /// The bus is opened once and shared by all devices on it.
+    pub fn i2c_bus(&mut self, bus: u32) -> Result<SharedI2cBus, HardwareError> {
+        if let Some(shared) = self.i2c_buses.get(&bus) {
+            return Ok(shared.clone());
+        }
+        let shared = Arc::new(Mutex::new(self.gpio.get_i2c_bus(bus)?));
+        self.i2c_buses.insert(bus, shared.clone());
+        Ok(shared)
+    }
+
+    /// Reset the MCP23017s with the given output pins, as `(bus, address, pin, high)` where
+    /// `high` is the safe level of the pin. All other pins of the expanders are made inputs.
+    pub fn reset_mcp23017s(
+        &mut self,
+        outputs: impl IntoIterator<Item = (u32, SevenBitAddress, u8, bool)>,
+    ) -> Result<(), HardwareError> {
+        let mut expanders: HashMap<(u32, SevenBitAddress), (u16, u16)> = HashMap::new();
+        for (bus, address, pin, high) in outputs {
+            let (outputs, latch) = expanders.entry((bus, address)).or_default();
+            // Invalid pins are reported when the pin is requested.
+            let mask = 1u16.checked_shl(pin.into()).unwrap_or(0);
+            *outputs |= mask;
+            if high {
+                *latch |= mask;
+            }
+        }
+        for ((bus, address), (outputs, latch)) in expanders {
+            let shared = self.i2c_bus(bus)?;
+            mcp23017::reset(&shared, address, outputs, latch)?;
+        }
+        Ok(())
+    }
+
+    /// Pin of an MCP23017, on a bus shared with the other pins.
+    pub fn mcp23017_pin(
+        &mut self,
+        bus: u32,
+        address: SevenBitAddress,
+        pin: u8,
+    ) -> Result<mcp23017::Mcp23017Pin<i2c::I2cBus>, HardwareError> {
+        let shared = self.i2c_bus(bus)?;
+        mcp23017::Mcp23017Pin::try_new(shared, address, pin)
+    }
+}
+
+pub enum GpioPin {
+    Cdev(LineHandle),
+    Dummy(dummy::GpioPin),
+    Mock(mock::MockPin),
+}
+
+impl OutputPin for GpioPin {
+    type Error = HardwareError;
+
+    fn try_set_low(&mut self) -> Result<(), Self::Error> {
+        match self {
+            GpioPin::Cdev(handle) => Ok(handle.set_value(0)?),
+            GpioPin::Dummy(pin) => Ok(pin.try_set_low()?),
+            GpioPin::Mock(pin) => {
+                pin.set(false);
+                Ok(())
+            }
+        }
+    }
+
+    fn try_set_high(&mut self) -> Result<(), Self::Error> {
+        match self {
+            GpioPin::Cdev(handle) => Ok(handle.set_value(1)?),
+            GpioPin::Dummy(pin) => Ok(pin.try_set_high()?),
+            GpioPin::Mock(pin) => {
+                pin.set(true);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl InputPin for GpioPin {
+    type Error = HardwareError;
+
+    fn try_is_high(&self) -> Result<bool, Self::Error> {
+        match self {
+            GpioPin::Cdev(handle) => Ok(handle.get_value()? != 0),
+            GpioPin::Dummy(pin) => Ok(pin.try_is_high()?),
+            GpioPin::Mock(pin) => Ok(pin.get()),
+        }
+    }
+
+    fn try_is_low(&self) -> Result<bool, Self::Error> {
+        self.try_is_high().map(|high| !high)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum HardwareError {
+    #[error("GPIO error: {0}")]
+    Gpio(#[from] gpio_cdev::errors::Error),
+    #[error("I2C error: {0}")]
+    I2c(String),
+}
+
+impl From<LinuxI2CError> for HardwareError {
+    fn from(err: LinuxI2CError) -> Self {
+        HardwareError::I2c(err.to_string())
+    }
+}
@@ -32,10 +32,13 @@
 
 mod actor;
 pub mod control;
-mod hardware;
+pub mod fermentation;
+pub mod hardware;
 mod logger;
+pub mod power;
 pub mod pub_sub;
 pub mod sensor;
+pub mod sequencer;
 pub mod supervisor;
-mod time;
+pub mod time;
 pub mod utils;
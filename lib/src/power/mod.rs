@@ -0,0 +1,216 @@
+//! Power budget for the whole brewery
+//!
+//! Actors which opt in with `power_budget` share a budget, e.g. the max. power of a single
+//! circuit.
+//! Their requested signals are granted in priority order, as far as the budget allows.
+use crate::actor::ActorConfig;
+use crate::pub_sub::ClientId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub mod pub_sub;
+pub use pub_sub::PowerManager;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PowerBudgetConfig {
+    /// Max. total power in W.
+    pub(crate) max_power: f32,
+    /// Actors served first, in order. Other actors in the budget follow in config order.
+    #[serde(default)]
+    pub(crate) priority: Vec<ClientId>,
+    /// Time after which a request is dropped, unless it is repeated.
+    /// Controllers repeat theirs every update period.
+    #[serde(default = "default_request_timeout_ms")]
+    pub(crate) request_timeout_ms: u64,
+}
+
+fn default_request_timeout_ms() -> u64 {
+    10_000
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BudgetedActor {
+    id: ClientId,
+    rated_power: f32,
+    /// Only fully on or off, so that a partial grant is not possible.
+    on_off: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grant {
+    pub requested: f32,
+    pub granted: f32,
+}
+
+pub struct PowerBudget {
+    max_power: f32,
+    actors: Vec<BudgetedActor>,
+    request_timeout: Duration,
+}
+
+impl PowerBudget {
+    pub fn new(config: &PowerBudgetConfig, actors: &[ActorConfig]) -> Self {
+        let mut actors: Vec<BudgetedActor> = actors
+            .iter()
+            .filter_map(|actor| {
+                actor.budgeted_power().map(|rated_power| BudgetedActor {
+                    id: actor.id.clone(),
+                    rated_power,
+                    on_off: actor.type_.is_on_off(),
+                })
+            })
+            .collect();
+        // Stable sort, which keeps the config order of actors without priority.
+        actors.sort_by_key(|actor| {
+            config
+                .priority
+                .iter()
+                .position(|id| *id == actor.id)
+                .unwrap_or(usize::MAX)
+        });
+        PowerBudget {
+            max_power: config.max_power,
+            actors,
+            request_timeout: Duration::from_millis(config.request_timeout_ms),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    pub fn actor_ids(&self) -> impl Iterator<Item = &ClientId> {
+        self.actors.iter().map(|actor| &actor.id)
+    }
+
+    pub fn rated_power(&self, id: &ClientId) -> Option<f32> {
+        self.actors
+            .iter()
+            .find(|actor| actor.id == *id)
+            .map(|actor| actor.rated_power)
+    }
+
+    /// Granted signals for the requested ones, with missing requests taken as off.
+    pub fn allocate(&self, requests: &HashMap<ClientId, f32>) -> HashMap<ClientId, Grant> {
+        let mut remaining = self.max_power;
+        self.actors
+            .iter()
+            .map(|actor| {
+                let requested = requests.get(&actor.id).copied().unwrap_or(0.0).max(0.0);
+                let available = (remaining / actor.rated_power).max(0.0);
+                let granted = if requested <= available {
+                    requested
+                } else if actor.on_off {
+                    0.0
+                } else {
+                    available
+                };
+                remaining -= granted * actor.rated_power;
+                (actor.id.clone(), Grant { requested, granted })
+            })
+            .collect()
+    }
+
+    /// Whether `signal` fits in the budget with the other actors at the signals `in_use`.
+    pub fn fits(&self, id: &ClientId, signal: f32, in_use: &HashMap<ClientId, f32>) -> bool {
+        let power: f32 = self
+            .actors
+            .iter()
+            .map(|actor| {
+                let signal = if actor.id == *id {
+                    signal
+                } else {
+                    in_use.get(&actor.id).copied().unwrap_or(0.0)
+                };
+                signal * actor.rated_power
+            })
+            .sum();
+        power <= self.max_power
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn budget() -> PowerBudget {
+        let actors: Vec<ActorConfig> = serde_json::from_str(
+            r#"
+            [
+              {"id": "hlt", "type": {"simple_gpio": 0}, "rated_power": 3500.0,
+               "power_budget": true},
+              {"id": "boil", "type": {"time_proportional": {"pin_number": 1, "window_ms": 5000}},
+               "rated_power": 3500.0, "power_budget": true},
+              {"id": "pump", "type": {"simple_gpio": 2}, "rated_power": 100.0}
+            ]"#,
+        )
+        .unwrap();
+        let config = PowerBudgetConfig {
+            max_power: 3680.0,
+            priority: vec![ClientId("boil".into())],
+            request_timeout_ms: default_request_timeout_ms(),
+        };
+        PowerBudget::new(&config, &actors)
+    }
+
+    fn requests(requests: &[(&str, f32)]) -> HashMap<ClientId, f32> {
+        requests
+            .iter()
+            .map(|(id, signal)| (ClientId((*id).into()), *signal))
+            .collect()
+    }
+
+    #[test]
+    fn test_priority() {
+        let budget = budget();
+        assert_eq!(
+            budget.actor_ids().collect::<Vec<_>>(),
+            vec![&ClientId("boil".into()), &ClientId("hlt".into())]
+        );
+        let grants = budget.allocate(&requests(&[("hlt", 1.0), ("boil", 1.0)]));
+        assert_approx_eq!(grants[&ClientId("boil".into())].granted, 1.0);
+        assert_approx_eq!(grants[&ClientId("hlt".into())].requested, 1.0);
+        assert_approx_eq!(grants[&ClientId("hlt".into())].granted, 0.0);
+
+        let grants = budget.allocate(&requests(&[("hlt", 1.0)]));
+        assert_approx_eq!(grants[&ClientId("boil".into())].granted, 0.0);
+        assert_approx_eq!(grants[&ClientId("hlt".into())].granted, 1.0);
+    }
+
+    #[test]
+    fn test_partial_grant() {
+        let budget = budget();
+        let grants = budget.allocate(&requests(&[("hlt", 1.0), ("boil", 0.5)]));
+        assert_approx_eq!(grants[&ClientId("boil".into())].granted, 0.5);
+        assert_approx_eq!(grants[&ClientId("hlt".into())].granted, 0.0);
+
+        let budget = PowerBudget {
+            max_power: 5000.0,
+            ..budget
+        };
+        let grants = budget.allocate(&requests(&[("hlt", 1.0), ("boil", 1.0)]));
+        assert_approx_eq!(grants[&ClientId("boil".into())].granted, 1.0);
+        assert_approx_eq!(grants[&ClientId("hlt".into())].granted, 0.0);
+
+        let budget = PowerBudget {
+            actors: budget.actors.into_iter().rev().collect(),
+            ..budget
+        };
+        let grants = budget.allocate(&requests(&[("hlt", 1.0), ("boil", 1.0)]));
+        assert_approx_eq!(grants[&ClientId("hlt".into())].granted, 1.0);
+        assert_approx_eq!(grants[&ClientId("boil".into())].granted, 1500.0 / 3500.0);
+    }
+
+    #[test]
+    fn test_fits() {
+        let budget = budget();
+        let hlt = ClientId("hlt".into());
+        let boil = ClientId("boil".into());
+        assert!(budget.fits(&hlt, 1.0, &requests(&[("hlt", 0.0), ("boil", 0.0)])));
+        // The boil element has not switched off yet.
+        assert!(!budget.fits(&hlt, 1.0, &requests(&[("boil", 0.5)])));
+        assert!(budget.fits(&boil, 0.5, &requests(&[("hlt", 0.0), ("pump", 1.0)])));
+    }
+}
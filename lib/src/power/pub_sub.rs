@@ -0,0 +1,281 @@
+use crate::actor::pub_sub::{ActorPubMsg, ActorSubMsg, SignalMsg};
+use crate::logger::{error, info};
+use crate::power::{Grant, PowerBudget};
+use crate::pub_sub::{
+    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsConfig, ClientId,
+    ClientState, PubSubClient, PubSubError, PubSubMsg, Subject,
+};
+use crate::supervisor::lease::Lease;
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::TimeStamp;
+use nats::{Message, Subscription};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Max. time between checks for a kill command.
+const POLL_PERIOD: Duration = Duration::from_millis(500);
+
+/// Arbitrates the signals requested on `actor.<id>.set_signal` for the actors in the budget,
+/// and forwards the granted ones.
+///
+/// Reduced grants are forwarded right away, while an increase waits until the signals
+/// applied by the actors leave room for it. This way, the budget is not exceeded while
+/// actors are switched over.
+pub struct PowerManager {
+    id: ClientId,
+    budget: PowerBudget,
+    /// Latest request per actor, with the time it was received.
+    requests: HashMap<ClientId, (SignalMsg, TimeStamp)>,
+    grants: HashMap<ClientId, Grant>,
+    /// Latest signal forwarded to each actor.
+    forwarded: HashMap<ClientId, f32>,
+    /// Latest signal applied by each actor, from its `current_signal`.
+    applied: HashMap<ClientId, f32>,
+    client: NatsClient,
+}
+
+impl PowerManager {
+    pub fn new(id: ClientId, budget: PowerBudget, config: &NatsConfig) -> Self {
+        let client = NatsClient::try_new(config).unwrap();
+        PowerManager {
+            id,
+            budget,
+            requests: HashMap::new(),
+            grants: HashMap::new(),
+            forwarded: HashMap::new(),
+            applied: HashMap::new(),
+            client,
+        }
+    }
+
+    fn handle_actor_msg(&mut self, msg: Message) -> Result<(), PubSubError> {
+        let (id, kind) = match msg.subject.split('.').collect::<Vec<_>>()[..] {
+            ["actor", id, kind] => (ClientId(id.into()), String::from(kind)),
+            _ => return Ok(()),
+        };
+        if self.budget.rated_power(&id).is_none() {
+            return Ok(());
+        }
+        match kind.as_str() {
+            "set_signal" => match ActorSubMsg::try_from(msg)? {
+                ActorSubMsg::SetSignal(msg) => self.request(msg),
+            },
+            "current_signal" => {
+                let msg: SignalMsg = decode_nats_data(&msg.data)?;
+                self.applied.insert(id, msg.signal);
+                self.forward(None)
+            }
+            "watchdog" | "interlock" => match decode_nats_data(&msg.data)? {
+                ActorPubMsg::Watchdog { .. } => self.drop_request(&id, "the watchdog tripped"),
+                ActorPubMsg::Interlock { .. } => self.drop_request(&id, "an interlock holds"),
+                ActorPubMsg::CurrentSignal(_) | ActorPubMsg::Fault { .. } => Ok(()),
+            },
+            "lease" => {
+                let lease: Lease = decode_nats_data(&msg.data)?;
+                let allowed = self.requests.get(&id).map_or(true, |(request, _)| {
+                    lease.allows(request.source.as_ref(), TimeStamp::now())
+                });
+                if allowed {
+                    Ok(())
+                } else {
+                    self.drop_request(&id, "the actor is leased to another client")
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn request(&mut self, msg: SignalMsg) -> Result<(), PubSubError> {
+        let id = msg.id.clone();
+        self.requests.insert(id.clone(), (msg, TimeStamp::now()));
+        self.reallocate(Some(&id))
+    }
+
+    fn drop_request(&mut self, id: &ClientId, reason: &str) -> Result<(), PubSubError> {
+        if self.requests.remove(id).is_none() {
+            return Ok(());
+        }
+        info(
+            self,
+            format!("Request of '{}' dropped, {}", id, reason),
+            "power",
+        );
+        self.reallocate(None)
+    }
+
+    /// Drop the requests which have not been repeated within the timeout.
+    fn expire_requests(&mut self, now: TimeStamp) -> Result<(), PubSubError> {
+        let timeout = self.budget.request_timeout().as_secs_f32();
+        let expired: Vec<ClientId> = self
+            .requests
+            .iter()
+            .filter(|(_, (_, received))| now.secs_since(*received) >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.drop_request(&id, "it was not repeated")?;
+        }
+        Ok(())
+    }
+
+    /// Allocate the budget to the current requests, and publish the changed grants.
+    fn reallocate(&mut self, requester: Option<&ClientId>) -> Result<(), PubSubError> {
+        let signals = self
+            .requests
+            .iter()
+            .map(|(id, (request, _))| (id.clone(), request.signal))
+            .collect();
+        let grants = self.budget.allocate(&signals);
+        for (id, grant) in grants.iter() {
+            if requester == Some(id) || self.grants.get(id) != Some(grant) {
+                let rated_power = self.budget.rated_power(id).unwrap_or(0.0);
+                let status = PowerPubMsg::Power(PowerStatus {
+                    id: id.clone(),
+                    timestamp: TimeStamp::now(),
+                    requested_power: grant.requested * rated_power,
+                    granted_power: grant.granted * rated_power,
+                });
+                self.publish(&status.subject(), &status.into())?;
+            }
+        }
+        self.grants = grants;
+        self.forward(requester)
+    }
+
+    /// Forward the grants which differ from the signals last forwarded, and the grant of the
+    /// requester, which keeps the watchdog of its actor fed.
+    fn forward(&mut self, requester: Option<&ClientId>) -> Result<(), PubSubError> {
+        let mut in_use: HashMap<ClientId, f32> = self
+            .budget
+            .actor_ids()
+            .map(|id| {
+                let applied = self.applied.get(id).copied().unwrap_or(0.0);
+                let forwarded = self.forwarded.get(id).copied().unwrap_or(0.0);
+                (id.clone(), applied.max(forwarded))
+            })
+            .collect();
+        let mut forwarded = Vec::new();
+        // In priority order, so that held back increases go to the first actor they fit.
+        for id in self.budget.actor_ids() {
+            let grant = match self.grants.get(id) {
+                Some(grant) => grant,
+                None => continue,
+            };
+            let previous = self.forwarded.get(id).copied().unwrap_or(0.0);
+            if grant.granted > previous && !self.budget.fits(id, grant.granted, &in_use) {
+                continue;
+            }
+            if grant.granted != previous || requester == Some(id) {
+                let signal = ActorSubMsg::SetSignal(SignalMsg {
+                    id: id.clone(),
+                    timestamp: TimeStamp::now(),
+                    signal: grant.granted,
+                    moving: false,
+                    unknown: false,
+                    // Granted on behalf of the requesting client, which must hold the lease.
+                    source: self
+                        .requests
+                        .get(id)
+                        .and_then(|(request, _)| request.source.clone()),
+                });
+                self.publish(&PowerPubMsg::grant_subject(id), &signal.into())?;
+                forwarded.push((id.clone(), grant.granted));
+                if grant.granted > previous {
+                    in_use.insert(id.clone(), grant.granted);
+                }
+            }
+        }
+        self.forwarded.extend(forwarded);
+        Ok(())
+    }
+}
+
+impl PubSubClient for PowerManager {
+    fn client_loop(mut self) -> Result<(), PubSubError> {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let actors = self.subscribe(&Subject(String::from("actor.*.*")))?;
+        info(
+            &self,
+            format!(
+                "Starting power manager for {}",
+                self.budget
+                    .actor_ids()
+                    .map(|id| format!("'{}'", id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            "power",
+        );
+        let mut state = ClientState::Active;
+        while state == ClientState::Active {
+            if let Some(msg) = kill_cmd.try_next() {
+                info(&self, String::from("Stopping power manager"), "power");
+                msg.respond("").map_err(|err| PubSubError::Reply {
+                    msg: msg.to_string(),
+                    err: err.to_string(),
+                })?;
+                state = ClientState::Inactive;
+                continue;
+            }
+            if let Ok(msg) = actors.next_timeout(POLL_PERIOD) {
+                if let Err(err) = self.handle_actor_msg(msg) {
+                    error(&self, err.to_string(), "power");
+                }
+            }
+            if let Err(err) = self.expire_requests(TimeStamp::now()) {
+                error(&self, err.to_string(), "power");
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        self.client.subscribe(subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        self.client.publish(subject, msg)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PowerPubMsg {
+    #[serde(rename = "power")]
+    Power(PowerStatus),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PowerStatus {
+    pub(crate) id: ClientId,
+    pub(crate) timestamp: TimeStamp,
+    /// Requested power in W.
+    pub(crate) requested_power: f32,
+    /// Granted power in W.
+    pub(crate) granted_power: f32,
+}
+
+impl PowerPubMsg {
+    /// Subject on which actors in the budget receive their granted signals.
+    pub fn grant_subject(id: &ClientId) -> Subject {
+        Subject(format!("actor.{}.granted_signal", id))
+    }
+
+    pub fn subject(&self) -> Subject {
+        match self {
+            PowerPubMsg::Power(status) => Subject(format!("actor.{}.power", status.id)),
+        }
+    }
+}
+
+impl Into<PubSubMsg> for PowerPubMsg {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
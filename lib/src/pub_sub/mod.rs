@@ -16,6 +16,12 @@ pub trait PubSubClient {
     fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError>;
 }
 
+/// Connection a client subscribes and publishes through, e.g. `NatsClient`.
+pub trait PubSubBackend: Send {
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError>;
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError>;
+}
+
 #[derive(From, Serialize, Deserialize, Display, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ClientId(pub String);
 
@@ -36,7 +42,7 @@ impl From<&str> for ClientId {
         String::from(x).into()
     }
 }
-#[derive(Display, Debug, Clone)]
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
 pub struct Subject(pub String);
 
 impl AsRef<str> for Subject {
@@ -1,4 +1,4 @@
-use crate::pub_sub::{PubSubError, PubSubMsg, Subject};
+use crate::pub_sub::{PubSubBackend, PubSubError, PubSubMsg, Subject};
 use nats::{Connection, Options, Subscription};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::any::type_name;
@@ -81,6 +81,16 @@ impl NatsClient {
     }
 }
 
+impl PubSubBackend for NatsClient {
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        NatsClient::subscribe(self, subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        NatsClient::publish(self, subject, msg)
+    }
+}
+
 pub fn run_nats_server(config: &NatsConfig) -> Result<Child, PubSubError> {
     let child = Command::new(&config.bin_path)
         .arg("-c")
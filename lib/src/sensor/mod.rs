@@ -3,7 +3,8 @@ pub mod cpu_temp;
 pub mod ds18b20;
 pub mod dummy;
 mod pub_sub;
-use crate::pub_sub::{ClientId, PubSubError};
+pub mod simulated_vessel;
+use crate::pub_sub::{nats_client::NatsConfig, ClientId, PubSubError};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -17,7 +18,7 @@ pub trait Sensor: Send {
     fn get_id(&self) -> String;
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum SensorType {
     #[serde(rename = "dummy")]
     Dummy(u64),
@@ -25,6 +26,8 @@ pub enum SensorType {
     Dsb(ds18b20::Ds18b20Address),
     #[serde(rename = "rbpi_cpu")]
     RbpiCPU,
+    #[serde(rename = "simulated_vessel")]
+    SimulatedVessel(simulated_vessel::SimulatedVesselConfig),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -35,7 +38,7 @@ pub struct SensorConfig {
 }
 
 impl SensorConfig {
-    pub fn get_sensor(&self) -> Result<Box<dyn Sensor>, SensorError> {
+    pub fn get_sensor(&self, nats_config: &NatsConfig) -> Result<Box<dyn Sensor>, SensorError> {
         match &self.type_ {
             SensorType::Dummy(delay_in_ms) => {
                 let sensor = dummy::DummySensor::new(self.id.as_ref(), *delay_in_ms);
@@ -49,6 +52,14 @@ impl SensorConfig {
                 let sensor = cpu_temp::CpuTemp::new(self.id.as_ref());
                 Ok(Box::new(sensor))
             }
+            SensorType::SimulatedVessel(config) => {
+                let sensor = simulated_vessel::SimulatedVessel::try_new(
+                    self.id.as_ref(),
+                    config,
+                    nats_config,
+                )?;
+                Ok(Box::new(sensor))
+            }
         }
     }
 }
@@ -69,6 +80,8 @@ pub enum SensorError {
     InvalidParam(String),
     #[error("Unknown sensor: {0}")]
     UnknownSensor(String),
+    #[error("Pub sub error: {0}")]
+    PubSub(String),
 }
 
 impl From<SensorError> for PubSubError {
@@ -0,0 +1,205 @@
+use crate::actor::pub_sub::SignalMsg;
+use crate::pub_sub::{
+    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsConfig, ClientId,
+    Subject,
+};
+use crate::sensor::{Sensor, SensorError};
+use nats::Subscription;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Specific heat capacity of water in J/(kg K).
+const SPECIFIC_HEAT: f32 = 4186.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VesselParams {
+    /// Volume of water in litres.
+    pub volume: f32,
+    /// Heater power at full signal in W.
+    pub heater_power: f32,
+    /// Heat loss to the ambient in W/K.
+    pub heat_loss: f32,
+    /// Time in seconds before a change in signal starts to affect the temperature.
+    #[serde(default)]
+    pub dead_time: f32,
+    #[serde(default = "default_ambient")]
+    pub ambient: f32,
+}
+
+fn default_ambient() -> f32 {
+    20.0
+}
+
+/// Lumped thermal model of a vessel with a heater
+///
+/// `c m dT/dt = P u(t - dead_time) - k (T - T_ambient)`
+pub struct VesselModel {
+    params: VesselParams,
+    temperature: f32,
+    time: f32,
+    /// Signals not yet past the dead time, as `(time, signal)`.
+    signals: VecDeque<(f32, f32)>,
+    delayed_signal: f32,
+}
+
+impl VesselModel {
+    pub fn new(params: VesselParams, temperature: f32) -> Self {
+        VesselModel {
+            params,
+            temperature,
+            time: 0.0,
+            signals: VecDeque::new(),
+            delayed_signal: 0.0,
+        }
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    /// Apply `signal` for `dt` seconds and return the new temperature.
+    pub fn step(&mut self, signal: f32, dt: f32) -> f32 {
+        self.signals.push_back((self.time, signal));
+        let delayed_time = self.time - self.params.dead_time;
+        self.time += dt;
+        while let Some((time, signal)) = self.signals.front() {
+            if *time > delayed_time {
+                break;
+            }
+            self.delayed_signal = *signal;
+            self.signals.pop_front();
+        }
+
+        let heat_capacity = SPECIFIC_HEAT * self.params.volume;
+        let power = self.params.heater_power * self.delayed_signal
+            - self.params.heat_loss * (self.temperature - self.params.ambient);
+        self.temperature += power / heat_capacity * dt;
+        self.temperature
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimulatedVesselConfig {
+    /// Actor heating the vessel.
+    pub(crate) actor_id: ClientId,
+    #[serde(flatten)]
+    pub(crate) params: VesselParams,
+    /// Initial temperature, defaults to the ambient.
+    #[serde(default)]
+    pub(crate) initial: Option<f32>,
+    #[serde(default = "default_delay_in_ms")]
+    pub(crate) delay_in_ms: u64,
+}
+
+fn default_delay_in_ms() -> u64 {
+    1000
+}
+
+/// Sensor measuring a simulated vessel, heated by the signals of a real actor client.
+pub struct SimulatedVessel {
+    pub id: String,
+    model: VesselModel,
+    signal: f32,
+    delay: Duration,
+    previous_time: Instant,
+    // Kept alive for the subscription.
+    _client: NatsClient,
+    signals: Subscription,
+}
+
+impl SimulatedVessel {
+    pub fn try_new(
+        id: &str,
+        config: &SimulatedVesselConfig,
+        nats_config: &NatsConfig,
+    ) -> Result<SimulatedVessel, SensorError> {
+        let client =
+            NatsClient::try_new(nats_config).map_err(|err| SensorError::PubSub(err.to_string()))?;
+        let signals = client
+            .subscribe(&Subject(format!(
+                "actor.{}.current_signal",
+                config.actor_id
+            )))
+            .map_err(|err| SensorError::PubSub(err.to_string()))?;
+        let initial = config.initial.unwrap_or(config.params.ambient);
+        Ok(SimulatedVessel {
+            id: id.into(),
+            model: VesselModel::new(config.params.clone(), initial),
+            signal: 0.0,
+            delay: Duration::from_millis(config.delay_in_ms),
+            previous_time: Instant::now(),
+            _client: client,
+            signals,
+        })
+    }
+}
+
+impl Sensor for SimulatedVessel {
+    fn get_measurement(&mut self) -> Result<f32, SensorError> {
+        sleep(self.delay);
+        for msg in self.signals.try_iter() {
+            let msg: SignalMsg =
+                decode_nats_data(&msg.data).map_err(|err| SensorError::Parse(err.to_string()))?;
+            self.signal = msg.signal;
+        }
+        let now = Instant::now();
+        let dt = (now - self.previous_time).as_secs_f32();
+        self.previous_time = now;
+        Ok(self.model.step(self.signal, dt))
+    }
+
+    fn get_id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn params(dead_time: f32) -> VesselParams {
+        VesselParams {
+            volume: 10.0,
+            heater_power: 4186.0,
+            heat_loss: 0.0,
+            dead_time,
+            ambient: 20.0,
+        }
+    }
+
+    #[test]
+    fn test_heating() {
+        let mut model = VesselModel::new(params(0.0), 20.0);
+        for _ in 0..100 {
+            model.step(1.0, 1.0);
+        }
+        assert_approx_eq!(model.temperature(), 30.0, 1e-3);
+    }
+
+    #[test]
+    fn test_dead_time() {
+        let mut model = VesselModel::new(params(5.0), 20.0);
+        for _ in 0..5 {
+            model.step(1.0, 1.0);
+        }
+        assert_approx_eq!(model.temperature(), 20.0);
+        model.step(1.0, 1.0);
+        assert_approx_eq!(model.temperature(), 20.1);
+    }
+
+    #[test]
+    fn test_heat_loss() {
+        let mut model = VesselModel::new(
+            VesselParams {
+                heat_loss: 41.86,
+                ..params(0.0)
+            },
+            60.0,
+        );
+        model.step(0.0, 1.0);
+        assert_approx_eq!(model.temperature(), 60.0 - 0.04);
+    }
+}
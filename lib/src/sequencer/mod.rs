@@ -0,0 +1,262 @@
+//! Mash schedule sequencer
+//!
+//! Steps through a list of temperature steps by setting the target of a running controller.
+//! The hold timer of a step is only started once the measurement has reached the step target.
+use crate::pub_sub::ClientId;
+use serde::{Deserialize, Serialize};
+
+pub mod pub_sub;
+pub use pub_sub::SequenceClient;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MashStep {
+    pub(crate) target: f32,
+    /// Ramp rate in °C/min. Without it, the target is set at once.
+    #[serde(default)]
+    pub(crate) ramp_rate: Option<f32>,
+    /// Hold duration in seconds.
+    pub(crate) hold_duration: u64,
+    /// Wait for the user to resume the sequence after the hold.
+    #[serde(default)]
+    pub(crate) wait_for_user: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SequenceConfig {
+    pub(crate) sequence_id: ClientId,
+    pub(crate) controller_id: ClientId,
+    pub(crate) steps: Vec<MashStep>,
+    /// Max. distance between measurement and target for the target to count as reached.
+    #[serde(default = "default_tolerance")]
+    pub(crate) tolerance: f32,
+}
+
+fn default_tolerance() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    #[serde(rename = "ramping")]
+    Ramping,
+    #[serde(rename = "heating")]
+    Heating,
+    #[serde(rename = "holding")]
+    Holding,
+    #[serde(rename = "waiting_for_user")]
+    WaitingForUser,
+    #[serde(rename = "done")]
+    Done,
+}
+
+pub struct Sequence {
+    steps: Vec<MashStep>,
+    tolerance: f32,
+    step: usize,
+    phase: Phase,
+    paused: bool,
+    target: Option<f32>,
+    hold_remaining: f32,
+}
+
+impl Sequence {
+    pub fn new(steps: Vec<MashStep>, tolerance: f32) -> Self {
+        let mut sequence = Sequence {
+            steps,
+            tolerance,
+            step: 0,
+            phase: Phase::Done,
+            paused: false,
+            target: None,
+            hold_remaining: 0.0,
+        };
+        sequence.enter_step(0);
+        sequence
+    }
+
+    /// Advance the sequence `dt` seconds and return the current target.
+    pub fn update(&mut self, measurement: Option<f32>, dt: f32) -> Option<f32> {
+        if self.paused {
+            return self.target;
+        }
+        let step = match self.steps.get(self.step) {
+            Some(step) => step.clone(),
+            None => return self.target,
+        };
+        match self.phase {
+            Phase::Ramping => {
+                // The first ramp starts from the first available measurement.
+                let current = match self.target.or(measurement) {
+                    Some(current) => current,
+                    None => return None,
+                };
+                let max_change = step.ramp_rate.unwrap_or(f32::INFINITY).abs() / 60.0 * dt;
+                let diff = step.target - current;
+                if diff.abs() <= max_change {
+                    self.target = Some(step.target);
+                    self.phase = Phase::Heating;
+                } else {
+                    self.target = Some(current + max_change.copysign(diff));
+                }
+            }
+            Phase::Heating => {
+                if let Some(measurement) = measurement {
+                    if (measurement - step.target).abs() <= self.tolerance {
+                        self.phase = Phase::Holding;
+                    }
+                }
+            }
+            Phase::Holding => {
+                self.hold_remaining -= dt;
+                if self.hold_remaining <= 0.0 {
+                    self.hold_remaining = 0.0;
+                    if step.wait_for_user {
+                        self.phase = Phase::WaitingForUser;
+                    } else {
+                        self.enter_step(self.step + 1);
+                    }
+                }
+            }
+            Phase::WaitingForUser | Phase::Done => {}
+        }
+        self.target
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume a paused sequence, or continue after a step waiting for the user.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        if self.phase == Phase::WaitingForUser {
+            self.enter_step(self.step + 1);
+        }
+    }
+
+    pub fn skip(&mut self) {
+        if self.phase != Phase::Done {
+            self.enter_step(self.step + 1);
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn current_step(&self) -> usize {
+        self.step
+    }
+
+    pub fn num_steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn target(&self) -> Option<f32> {
+        self.target
+    }
+
+    pub fn hold_remaining(&self) -> f32 {
+        self.hold_remaining
+    }
+
+    fn enter_step(&mut self, step: usize) {
+        self.step = step;
+        match self.steps.get(step) {
+            Some(step) => {
+                self.hold_remaining = step.hold_duration as f32;
+                if step.ramp_rate.is_some() {
+                    self.phase = Phase::Ramping;
+                } else {
+                    self.target = Some(step.target);
+                    self.phase = Phase::Heating;
+                }
+            }
+            None => {
+                self.step = self.steps.len();
+                self.phase = Phase::Done;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn step(target: f32, ramp_rate: Option<f32>, wait_for_user: bool) -> MashStep {
+        MashStep {
+            target,
+            ramp_rate,
+            hold_duration: 60,
+            wait_for_user,
+        }
+    }
+
+    #[test]
+    fn test_hold_starts_at_target() {
+        let mut sequence = Sequence::new(vec![step(65.0, None, false)], 0.5);
+        assert_approx_eq!(sequence.update(Some(50.0), 120.0).unwrap(), 65.0);
+        assert_eq!(sequence.phase(), Phase::Heating);
+        assert_approx_eq!(sequence.hold_remaining(), 60.0);
+        sequence.update(Some(64.6), 1.0);
+        assert_eq!(sequence.phase(), Phase::Holding);
+        sequence.update(Some(65.0), 30.0);
+        assert_approx_eq!(sequence.hold_remaining(), 30.0);
+        sequence.update(Some(65.0), 30.0);
+        assert_eq!(sequence.phase(), Phase::Done);
+    }
+
+    #[test]
+    fn test_ramp() {
+        let mut sequence = Sequence::new(
+            vec![step(65.0, None, false), step(72.0, Some(1.0), false)],
+            0.5,
+        );
+        sequence.skip();
+        assert_eq!(sequence.phase(), Phase::Ramping);
+        assert_approx_eq!(sequence.update(Some(65.0), 60.0).unwrap(), 66.0);
+        assert_approx_eq!(sequence.update(Some(66.0), 120.0).unwrap(), 68.0);
+        assert_approx_eq!(sequence.update(Some(68.0), 600.0).unwrap(), 72.0);
+        assert_eq!(sequence.phase(), Phase::Heating);
+    }
+
+    #[test]
+    fn test_first_ramp_from_measurement() {
+        let mut sequence = Sequence::new(vec![step(65.0, Some(2.0), false)], 0.5);
+        assert!(sequence.update(None, 60.0).is_none());
+        assert_approx_eq!(sequence.update(Some(40.0), 60.0).unwrap(), 42.0);
+    }
+
+    #[test]
+    fn test_wait_for_user() {
+        let mut sequence =
+            Sequence::new(vec![step(65.0, None, true), step(72.0, None, false)], 0.5);
+        sequence.update(Some(65.0), 1.0);
+        sequence.update(Some(65.0), 60.0);
+        assert_eq!(sequence.phase(), Phase::WaitingForUser);
+        sequence.update(Some(65.0), 600.0);
+        assert_eq!(sequence.phase(), Phase::WaitingForUser);
+        sequence.resume();
+        assert_eq!(sequence.current_step(), 1);
+        assert_approx_eq!(sequence.update(Some(65.0), 1.0).unwrap(), 72.0);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut sequence = Sequence::new(vec![step(65.0, None, false)], 0.5);
+        sequence.update(Some(65.0), 1.0);
+        sequence.pause();
+        sequence.update(Some(65.0), 600.0);
+        assert_eq!(sequence.phase(), Phase::Holding);
+        assert_approx_eq!(sequence.hold_remaining(), 60.0);
+        sequence.resume();
+        sequence.update(Some(65.0), 60.0);
+        assert_eq!(sequence.phase(), Phase::Done);
+    }
+}
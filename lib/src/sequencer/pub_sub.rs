@@ -0,0 +1,239 @@
+use crate::control::fusion::SensorFusion;
+use crate::control::pub_sub::ControllerSubMsg;
+use crate::control::ControllerInput;
+use crate::logger::{error, info};
+use crate::pub_sub::{
+    nats_client::decode_nats_data, nats_client::NatsClient, nats_client::NatsConfig, ClientId,
+    ClientState, PubSubClient, PubSubError, PubSubMsg, Subject,
+};
+use crate::sensor::SensorMsg;
+use crate::sequencer::{Phase, Sequence, SequenceConfig};
+use crate::supervisor::pub_sub::SupervisorPubMsg;
+use crate::time::TimeStamp;
+use nats::{Message, Subscription};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+const MAX_SENSOR_WAIT: Duration = Duration::from_millis(1000);
+/// Progress is published on every step change, and otherwise with this period.
+const PROGRESS_PERIOD: Duration = Duration::from_millis(1000);
+
+pub struct SequenceClient {
+    id: ClientId,
+    controller_id: ClientId,
+    fusion: SensorFusion,
+    sequence: Sequence,
+    client: NatsClient,
+}
+
+impl SequenceClient {
+    pub fn new(config: SequenceConfig, input: &ControllerInput, nats_config: &NatsConfig) -> Self {
+        let client = NatsClient::try_new(nats_config).unwrap();
+        SequenceClient {
+            id: config.sequence_id,
+            controller_id: config.controller_id,
+            fusion: input.fusion(),
+            sequence: Sequence::new(config.steps, config.tolerance),
+            client,
+        }
+    }
+
+    fn progress(&self) -> SequenceProgress {
+        SequenceProgress {
+            id: self.id.clone(),
+            timestamp: TimeStamp::now(),
+            step: self.sequence.current_step(),
+            num_steps: self.sequence.num_steps(),
+            phase: self.sequence.phase(),
+            paused: self.sequence.is_paused(),
+            target: self.sequence.target(),
+            hold_remaining: self.sequence.hold_remaining(),
+        }
+    }
+
+    fn process_command(&mut self, msg: Message) {
+        match SequenceSubMsg::try_from(msg) {
+            Ok(cmd) => {
+                info(
+                    self,
+                    format!("Sequence command: {:?}", cmd),
+                    &format!("sequence.{}", self.id),
+                );
+                match cmd {
+                    SequenceSubMsg::Pause => self.sequence.pause(),
+                    SequenceSubMsg::Resume => self.sequence.resume(),
+                    SequenceSubMsg::Skip => self.sequence.skip(),
+                }
+            }
+            Err(err) => error(self, err.to_string(), &format!("sequence.{}", self.id)),
+        }
+    }
+}
+
+impl PubSubClient for SequenceClient {
+    fn client_loop(mut self) -> Result<(), PubSubError> {
+        let kill_cmd = self.subscribe(
+            &SupervisorPubMsg::KillClient {
+                client_id: self.id.clone(),
+            }
+            .subject(),
+        )?;
+        let commands = self.subscribe(&SequenceSubMsg::subject(&self.id))?;
+        let sensor = self.subscribe(&self.fusion.subject())?;
+        info(
+            &self,
+            format!(
+                "Starting sequence '{}' on controller '{}'",
+                self.id, self.controller_id
+            ),
+            &format!("sequence.{}", self.id),
+        );
+        let mut state = ClientState::Active;
+        let mut last_update = Instant::now();
+        let mut last_target = None;
+        let mut last_progress: Option<Instant> = None;
+        while state == ClientState::Active {
+            if let Some(msg) = kill_cmd.try_next() {
+                info(
+                    &self,
+                    String::from("Aborting sequence"),
+                    &format!("sequence.{}", self.id),
+                );
+                let progress: PubSubMsg = SequencePubMsg::Progress(self.progress()).into();
+                msg.respond(progress.to_string())
+                    .map_err(|err| PubSubError::Reply {
+                        msg: msg.to_string(),
+                        err: err.to_string(),
+                    })?;
+                state = ClientState::Inactive;
+                continue;
+            }
+
+            for msg in commands.try_iter() {
+                self.process_command(msg);
+                last_progress = None;
+            }
+
+            if let Ok(msg) = sensor.next_timeout(MAX_SENSOR_WAIT) {
+                if let Ok(msg) = SensorMsg::try_from(msg) {
+                    self.fusion.update(msg);
+                }
+            }
+            let measurement = self.fusion.fused().map(|fused| fused.value);
+            let now = Instant::now();
+            let dt = (now - last_update).as_secs_f32();
+            last_update = now;
+
+            let previous_phase = self.sequence.phase();
+            let target = self.sequence.update(measurement, dt);
+            if target != last_target {
+                if let Some(target) = target {
+                    let msg = ControllerSubMsg::SetTarget(target);
+                    self.publish(&ControllerSubMsg::subject(&self.controller_id), &msg.into())?;
+                }
+                last_target = target;
+            }
+            if self.sequence.phase() != previous_phase {
+                last_progress = None;
+                info(
+                    &self,
+                    format!(
+                        "Step {}/{}: {:?}",
+                        self.sequence.current_step() + 1,
+                        self.sequence.num_steps(),
+                        self.sequence.phase()
+                    ),
+                    &format!("sequence.{}", self.id),
+                );
+            }
+
+            if last_progress.map_or(true, |last| last.elapsed() >= PROGRESS_PERIOD) {
+                let msg = SequencePubMsg::Progress(self.progress());
+                self.publish(&msg.subject(), &msg.into())?;
+                last_progress = Some(Instant::now());
+            }
+            if self.sequence.phase() == Phase::Done {
+                info(
+                    &self,
+                    String::from("Sequence done"),
+                    &format!("sequence.{}", self.id),
+                );
+                state = ClientState::Inactive;
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&self, subject: &Subject) -> Result<Subscription, PubSubError> {
+        self.client.subscribe(subject)
+    }
+
+    fn publish(&self, subject: &Subject, msg: &PubSubMsg) -> Result<(), PubSubError> {
+        self.client.publish(subject, msg)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SequenceSubMsg {
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "skip")]
+    Skip,
+}
+
+impl SequenceSubMsg {
+    pub fn subject(id: &ClientId) -> Subject {
+        Subject(format!("sequence.{}.command", id))
+    }
+}
+
+impl TryFrom<Message> for SequenceSubMsg {
+    type Error = PubSubError;
+    fn try_from(msg: Message) -> Result<Self, Self::Error> {
+        decode_nats_data(&msg.data)
+    }
+}
+
+impl Into<PubSubMsg> for SequenceSubMsg {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SequencePubMsg {
+    #[serde(rename = "progress")]
+    Progress(SequenceProgress),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SequenceProgress {
+    pub(crate) id: ClientId,
+    pub(crate) timestamp: TimeStamp,
+    pub(crate) step: usize,
+    pub(crate) num_steps: usize,
+    pub(crate) phase: Phase,
+    pub(crate) paused: bool,
+    pub(crate) target: Option<f32>,
+    /// Remaining hold time of the current step in seconds.
+    pub(crate) hold_remaining: f32,
+}
+
+impl SequencePubMsg {
+    pub fn subject(&self) -> Subject {
+        match self {
+            SequencePubMsg::Progress(progress) => {
+                Subject(format!("sequence.{}.progress", progress.id))
+            }
+        }
+    }
+}
+
+impl Into<PubSubMsg> for SequencePubMsg {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
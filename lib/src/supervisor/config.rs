@@ -1,5 +1,7 @@
-use crate::actor::ActorConfig;
+use crate::actor::{interlock::InterlockConfig, ActorConfig};
+use crate::hardware::GpioBackend;
 use crate::logger::LogLevel;
+use crate::power::PowerBudgetConfig;
 use crate::pub_sub::nats_client::NatsConfig;
 use crate::pub_sub::ClientId;
 use crate::sensor::ds18b20::Ds18b20Address;
@@ -8,7 +10,7 @@ use serde::{Deserialize, Serialize};
 use std::error as std_error;
 use std::fs;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SupervisorConfig {
@@ -22,6 +24,13 @@ pub struct General {
     pub brewery_name: String,
     // TODO: Rename all
     pub log_level: LogLevel,
+    /// Directory where the progress of long-running programs is persisted.
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+}
+
+fn default_state_dir() -> PathBuf {
+    PathBuf::from("state")
 }
 
 impl Default for General {
@@ -29,14 +38,24 @@ impl Default for General {
         General {
             brewery_name: "No name".into(),
             log_level: LogLevel::Info,
+            state_dir: default_state_dir(),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Hardware {
+    /// Backend of the GPIO pins, defaults to the first Linux GPIO chip.
+    #[serde(default)]
+    pub gpio: GpioBackend,
     pub actors: Vec<ActorConfig>,
     pub sensors: Vec<SensorConfig>,
+    /// Conditions on the actors, enforced regardless of what controllers ask for.
+    #[serde(default)]
+    pub interlocks: Vec<InterlockConfig>,
+    /// Max. total power of the actors with a rated power.
+    #[serde(default)]
+    pub power_budget: Option<PowerBudgetConfig>,
 }
 
 impl SupervisorConfig {
@@ -45,15 +64,24 @@ impl SupervisorConfig {
             general: General::default(),
             nats: NatsConfig::dummy(),
             hardware: Hardware {
+                gpio: GpioBackend::Dummy,
                 sensors: vec![SensorConfig {
                     id: ClientId("dummy".into()),
                     type_: SensorType::Dsb(Ds18b20Address::dummy()),
                 }],
                 actors: Vec::new(),
+                interlocks: Vec::new(),
+                power_budget: None,
             },
         }
     }
 
+    /// Run without touching any real pins.
+    pub fn dry_run(mut self) -> Self {
+        self.hardware.gpio = GpioBackend::Dummy;
+        self
+    }
+
     pub fn pprint(&self) -> String {
         //toml::ser::to_string_pretty(self).unwrap()
         serde_json::to_string_pretty(self).unwrap()
@@ -0,0 +1,269 @@
+//! Exclusive leases on actors, so that only one client drives an actor at a time
+//!
+//! Controllers hold a lease on their output actors while they are active, and actors reject
+//! signals from other clients. A lease can be taken over for a limited time, e.g. for manual
+//! control, after which it returns to its previous holder.
+//!
+//! The inner controller of a cascade is leased to the outer controller in the same way, but
+//! that lease is only checked by the supervisor, when controllers are started.
+use crate::pub_sub::{ClientId, PubSubMsg, Subject};
+use crate::time::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Lease state of a client, published on `actor.<id>.lease` when it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub(crate) client_id: ClientId,
+    /// Client allowed to drive the actor, `None` if the actor is free.
+    pub(crate) holder: Option<ClientId>,
+    /// End of a takeover, after which the lease returns to `next_holder`.
+    pub(crate) expires: Option<TimeStamp>,
+    #[serde(default)]
+    pub(crate) next_holder: Option<ClientId>,
+}
+
+impl Lease {
+    pub fn subject(actor_id: &ClientId) -> Subject {
+        Subject(format!("actor.{}.lease", actor_id))
+    }
+
+    pub fn holder(&self, now: TimeStamp) -> Option<&ClientId> {
+        match self.expires {
+            Some(expires) if now >= expires => self.next_holder.as_ref(),
+            _ => self.holder.as_ref(),
+        }
+    }
+
+    /// Whether a client other than `id` currently holds the lease.
+    pub fn held_by_other(&self, id: &ClientId, now: TimeStamp) -> bool {
+        self.holder(now).map_or(false, |holder| holder != id)
+    }
+
+    /// Whether a signal from `source` may drive the actor.
+    /// Signals without a source are only allowed while the actor is free.
+    pub fn allows(&self, source: Option<&ClientId>, now: TimeStamp) -> bool {
+        match self.holder(now) {
+            Some(holder) => source == Some(holder),
+            None => true,
+        }
+    }
+}
+
+impl Into<PubSubMsg> for Lease {
+    fn into(self) -> PubSubMsg {
+        PubSubMsg(serde_json::to_string(&self).expect("Pub sub serialization error"))
+    }
+}
+
+/// Time-limited lease, taking precedence over the regular one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Takeover {
+    pub(crate) actor_id: ClientId,
+    pub(crate) holder: ClientId,
+    pub(crate) duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Held {
+    holder: Option<ClientId>,
+    takeover: Option<(ClientId, TimeStamp)>,
+}
+
+impl Held {
+    fn active_takeover(&self, now: TimeStamp) -> Option<&(ClientId, TimeStamp)> {
+        self.takeover.as_ref().filter(|(_, expires)| now < *expires)
+    }
+
+    fn lease(&self, client_id: &ClientId, now: TimeStamp) -> Lease {
+        match self.active_takeover(now) {
+            Some((holder, expires)) => Lease {
+                client_id: client_id.clone(),
+                holder: Some(holder.clone()),
+                expires: Some(*expires),
+                next_holder: self.holder.clone(),
+            },
+            None => Lease {
+                client_id: client_id.clone(),
+                holder: self.holder.clone(),
+                expires: None,
+                next_holder: None,
+            },
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LeaseError {
+    #[error("'{client_id}' is leased to '{holder}'")]
+    Leased {
+        client_id: ClientId,
+        holder: ClientId,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Leases {
+    held: HashMap<ClientId, Held>,
+}
+
+impl Leases {
+    pub fn lease(&self, client_id: &ClientId, now: TimeStamp) -> Lease {
+        self.held
+            .get(client_id)
+            .map(|held| held.lease(client_id, now))
+            .unwrap_or(Lease {
+                client_id: client_id.clone(),
+                holder: None,
+                expires: None,
+                next_holder: None,
+            })
+    }
+
+    /// Lease all of `client_ids` to `holder`, or none of them if any is held by another client.
+    ///
+    /// A takeover does not prevent this, but the lease only takes effect when it expires.
+    /// This way, a controller can be switched during a takeover.
+    pub fn acquire(
+        &mut self,
+        holder: &ClientId,
+        client_ids: &[&ClientId],
+        now: TimeStamp,
+    ) -> Result<Vec<Lease>, LeaseError> {
+        for client_id in client_ids {
+            if let Some(held) = self.held.get(*client_id) {
+                if let Some(other) = held.holder.as_ref().filter(|other| *other != holder) {
+                    return Err(LeaseError::Leased {
+                        client_id: (*client_id).clone(),
+                        holder: other.clone(),
+                    });
+                }
+            }
+        }
+        Ok(client_ids
+            .iter()
+            .map(|client_id| {
+                let held = self.held.entry((*client_id).clone()).or_default();
+                held.holder = Some(holder.clone());
+                held.lease(client_id, now)
+            })
+            .collect())
+    }
+
+    /// Release all leases and takeovers of `holder`.
+    pub fn release(&mut self, holder: &ClientId, now: TimeStamp) -> Vec<Lease> {
+        let mut released = Vec::new();
+        for (client_id, held) in self.held.iter_mut() {
+            let mut changed = false;
+            if held.holder.as_ref() == Some(holder) {
+                held.holder = None;
+                changed = true;
+            }
+            if held.takeover.as_ref().map(|(other, _)| other) == Some(holder) {
+                held.takeover = None;
+                changed = true;
+            }
+            if changed {
+                released.push(held.lease(client_id, now));
+            }
+        }
+        self.held
+            .retain(|_, held| held.holder.is_some() || held.takeover.is_some());
+        released
+    }
+
+    /// Take over the lease of an actor, regardless of its current holder.
+    pub fn take_over(&mut self, takeover: &Takeover, now: TimeStamp) -> Lease {
+        let expires = now.add_secs(takeover.duration_ms as f32 / 1000.0);
+        let held = self.held.entry(takeover.actor_id.clone()).or_default();
+        held.takeover = Some((takeover.holder.clone(), expires));
+        held.lease(&takeover.actor_id, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(id: &str) -> ClientId {
+        ClientId(id.into())
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let mut leases = Leases::default();
+        let now = TimeStamp(0);
+        leases
+            .acquire(&id("mash"), &[&id("heater"), &id("pump")], now)
+            .unwrap();
+        assert_eq!(
+            leases.acquire(&id("hlt"), &[&id("hlt_heater"), &id("heater")], now),
+            Err(LeaseError::Leased {
+                client_id: id("heater"),
+                holder: id("mash")
+            })
+        );
+        // Nothing is leased on a conflict.
+        assert_eq!(leases.lease(&id("hlt_heater"), now).holder, None);
+        // Re-acquiring is fine.
+        assert!(leases.acquire(&id("mash"), &[&id("heater")], now).is_ok());
+
+        let released = leases.release(&id("mash"), now);
+        assert_eq!(released.len(), 2);
+        assert!(leases
+            .acquire(&id("hlt"), &[&id("hlt_heater"), &id("heater")], now)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_takeover() {
+        let mut leases = Leases::default();
+        leases
+            .acquire(&id("mash"), &[&id("heater")], TimeStamp(0))
+            .unwrap();
+        let takeover = Takeover {
+            actor_id: id("heater"),
+            holder: id("manual"),
+            duration_ms: 60_000,
+        };
+        let lease = leases.take_over(&takeover, TimeStamp(1000));
+        assert_eq!(lease.holder, Some(id("manual")));
+        assert_eq!(lease.expires, Some(TimeStamp(61_000)));
+        assert!(lease.held_by_other(&id("mash"), TimeStamp(60_999)));
+        assert!(!lease.held_by_other(&id("mash"), TimeStamp(61_000)));
+        assert!(!lease.held_by_other(&id("manual"), TimeStamp(1000)));
+        assert!(lease.allows(Some(&id("manual")), TimeStamp(60_999)));
+        assert!(!lease.allows(Some(&id("manual")), TimeStamp(61_000)));
+        assert!(lease.allows(Some(&id("mash")), TimeStamp(61_000)));
+        assert!(!lease.allows(None, TimeStamp(61_000)));
+        assert!(leases
+            .lease(&id("pump"), TimeStamp(0))
+            .allows(None, TimeStamp(0)));
+
+        assert!(leases
+            .acquire(&id("manual"), &[&id("heater")], TimeStamp(2000))
+            .is_err());
+        // A new controller waits for the takeover to expire.
+        leases.release(&id("mash"), TimeStamp(2000));
+        let lease = &leases
+            .acquire(&id("hlt"), &[&id("heater")], TimeStamp(2000))
+            .unwrap()[0];
+        assert_eq!(lease.holder, Some(id("manual")));
+        assert_eq!(
+            leases.lease(&id("heater"), TimeStamp(61_000)).holder,
+            Some(id("hlt"))
+        );
+
+        // The lease returns to the previous holder when the takeover expires.
+        let mut leases = Leases::default();
+        leases
+            .acquire(&id("mash"), &[&id("heater")], TimeStamp(0))
+            .unwrap();
+        leases.take_over(&takeover, TimeStamp(1000));
+        assert_eq!(
+            leases.lease(&id("heater"), TimeStamp(61_000)).holder,
+            Some(id("mash"))
+        );
+    }
+}
@@ -1,23 +1,34 @@
 pub mod config;
-use crate::actor::{ActorClient, ActorConfig, ActorError};
+pub mod lease;
+use crate::actor::{interlock::Interlocks, ActorClient, ActorConfig, ActorError};
 use crate::control::{
-    pub_sub::ControllerPubMsg, ControllerClient, ControllerConfig, ControllerError,
+    pub_sub::{ControllerOverride, ControllerPubMsg, ControllerStatus, ControllerSubMsg},
+    ControllerClient, ControllerConfig, ControllerError, ControllerOutput,
 };
+use crate::fermentation::{
+    self, FermentationClient, FermentationConfig, FermentationError, FermentationState, Profile,
+};
+use crate::hardware::{Devices, HardwareError};
 use crate::logger::Log;
 use crate::logger::{debug, error, info};
+use crate::power::{PowerBudget, PowerBudgetConfig, PowerManager};
 use crate::pub_sub::PubSubMsg;
 use crate::pub_sub::{
     nats_client::{decode_nats_data, NatsClient, NatsConfig},
     ClientId, ClientState, PubSubClient, PubSubError,
 };
 use crate::sensor::{SensorClient, SensorConfig, SensorError};
+use crate::sequencer::{pub_sub::SequenceSubMsg, SequenceClient, SequenceConfig};
+use crate::supervisor::lease::{Lease, LeaseError, Leases, Takeover};
 use crate::supervisor::pub_sub::{SupervisorPubMsg, SupervisorSubMsg};
-use crate::time::TimeStamp;
+use crate::time::{SystemClock, TimeStamp};
 use nats::Message;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::thread;
+use std::time::Duration;
 use thiserror::Error;
 
 pub mod pub_sub;
@@ -28,6 +39,9 @@ pub struct Supervisor {
     client: NatsClient,
     config: config::SupervisorConfig,
     active_clients: ActiveClients,
+    /// Hardware shared between actors, kept open for the lifetime of the supervisor.
+    devices: Devices,
+    leases: Leases,
 }
 
 impl Supervisor {
@@ -39,6 +53,8 @@ impl Supervisor {
             client,
             config: config.clone(),
             active_clients: ActiveClients::new(),
+            devices: Devices::new(config.hardware.gpio.clone()),
+            leases: Leases::default(),
         };
 
         supervisor.add_logger(&config)?;
@@ -47,10 +63,30 @@ impl Supervisor {
             supervisor.add_sensor(sensor_config, &config.nats)?;
         }
 
+        if let Some(budget) = &config.hardware.power_budget {
+            supervisor.add_power_manager(budget, &config.hardware.actors, &config.nats)?;
+        }
+
+        // All expander outputs are safe before any actor starts.
+        supervisor.devices.reset_mcp23017s(
+            config
+                .hardware
+                .actors
+                .iter()
+                .filter_map(ActorConfig::mcp23017_safe_level),
+        )?;
         for actor_config in config.hardware.actors {
             supervisor.add_actor(actor_config, &config.nats)?;
         }
 
+        if let Err(err) = supervisor.resume_fermentations() {
+            error(
+                &supervisor,
+                format!("Could not resume fermentation programs: {}", err),
+                "supervisor",
+            );
+        }
+
         info(&supervisor, String::from("Supervisor ready"), "supervisor");
         Ok(supervisor)
     }
@@ -60,9 +96,10 @@ impl Supervisor {
         cmd: SupervisorSubMsg,
         full_msg: &Message,
     ) -> Result<ClientState, SupervisorError> {
+        self.remove_done_sequences();
         match cmd {
             SupervisorSubMsg::StartController { contr_data } => {
-                self.start_controller(contr_data.config, contr_data.new_target)?;
+                self.start_controller(contr_data.config, contr_data.new_target, None)?;
                 Ok(ClientState::Active)
             }
             SupervisorSubMsg::SwitchController { contr_data } => {
@@ -80,6 +117,47 @@ impl Supervisor {
                 };
                 Ok(ClientState::Active)
             }
+            SupervisorSubMsg::StartSequence { config } => {
+                self.start_sequence(config)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::PauseSequence { sequence_id } => {
+                self.command_sequence(&sequence_id, SequenceSubMsg::Pause)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::ResumeSequence { sequence_id } => {
+                self.command_sequence(&sequence_id, SequenceSubMsg::Resume)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::SkipSequenceStep { sequence_id } => {
+                self.command_sequence(&sequence_id, SequenceSubMsg::Skip)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::AbortSequence { sequence_id } => {
+                self.abort_sequence(&sequence_id)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::StartFermentation { config } => {
+                self.start_fermentation(FermentationState::new(config, TimeStamp::now()))?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::StopFermentation { program_id } => {
+                self.stop_fermentation(&program_id)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::OverrideController { override_ } => {
+                self.override_controller(override_)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::TakeOverActor { takeover } => {
+                self.take_over_actor(takeover, full_msg)?;
+                Ok(ClientState::Active)
+            }
+            SupervisorSubMsg::NewSession => {
+                // The actors reset their stats themselves.
+                info(self, String::from("New brew session"), "supervisor");
+                Ok(ClientState::Active)
+            }
             SupervisorSubMsg::Stop => Ok(ClientState::Active),
         }
     }
@@ -88,6 +166,7 @@ impl Supervisor {
         &mut self,
         contr_config: ControllerConfig,
         target: f32,
+        prev_signal: Option<f32>,
     ) -> Result<(), SupervisorError> {
         contr_config
             .client_ids()
@@ -95,24 +174,64 @@ impl Supervisor {
             .collect::<Result<Vec<_>, SupervisorError>>()?;
 
         let id = &contr_config.controller_id;
+        if let ControllerOutput::Cascade(cascade) = &contr_config.output {
+            let inner_id = &cascade.controller_id;
+            if inner_id == id || !self.active_clients.controllers.contains_key(inner_id) {
+                return Err(SupervisorError::MissingController(inner_id.clone()));
+            }
+        }
+        if let ControllerOutput::SplitRange(split_range) = &contr_config.output {
+            let (heater_id, cooler_id) = (&split_range.heater_id, &split_range.cooler_id);
+            if !self
+                .config
+                .hardware
+                .interlocks
+                .iter()
+                .any(|interlock| interlock.excludes(heater_id, cooler_id))
+            {
+                return Err(SupervisorError::MissingInterlock(
+                    heater_id.clone(),
+                    cooler_id.clone(),
+                ));
+            }
+        }
         match self.active_clients.controllers.get(id) {
             Some(_) => Err(SupervisorError::AlreadyActive(id.clone())),
             None => {
+                let mut controller = contr_config.get_controller(target)?;
+                if let Some(prev_signal) = prev_signal {
+                    controller.transfer_from(prev_signal);
+                }
                 let controller_client = ControllerClient::new(
-                    id.clone(),
-                    contr_config.actor_id.clone(),
-                    contr_config.sensor_id.clone(),
-                    contr_config.get_controller(target)?,
+                    contr_config.clone(),
+                    controller,
                     &self.config.nats,
-                    contr_config.type_.clone(),
+                    Box::new(SystemClock),
                 );
+                // The inner controller of a cascade is leased as well, but only the actor
+                // leases are published.
+                let output_ids: Vec<&ClientId> = contr_config.output.client_ids();
+                let leases = self.leases.acquire(id, &output_ids, TimeStamp::now())?;
+                let actor_ids = contr_config.output.actor_ids();
+                let leases: Vec<Lease> = leases
+                    .into_iter()
+                    .filter(|lease| actor_ids.contains(&&lease.client_id))
+                    .collect();
+                let controller_client = match controller_client.with_leases(leases.clone()) {
+                    Ok(controller_client) => controller_client,
+                    Err(err) => {
+                        self.leases.release(id, TimeStamp::now());
+                        return Err(err.into());
+                    }
+                };
                 let control_handle =
                     thread::spawn(|| controller_client.client_loop().map_err(|err| err.into()));
+                // Registered before anything else can fail, so that it can always be stopped.
                 self.active_clients.controllers.insert(
                     contr_config.controller_id.clone(),
                     (control_handle, contr_config),
                 );
-                Ok(())
+                self.publish_leases(leases)
             }
         }
     }
@@ -132,14 +251,21 @@ impl Supervisor {
             "supervisor",
         );
         let contr_id = &config.controller_id;
-        let _: f32 = self.kill_client(contr_id)?;
-        self.start_controller(config.clone(), new_target)?;
-        let status: PubSubMsg = ControllerPubMsg::Status {
+        let prev_signal = match self.kill_client(contr_id)? {
+            ControllerPubMsg::Status(status) => Some(status.signal),
+            _ => None,
+        };
+        self.start_controller(config.clone(), new_target, prev_signal)?;
+        let status: PubSubMsg = ControllerPubMsg::Status(ControllerStatus {
             id: contr_id.clone(),
             timestamp: TimeStamp::now(),
             target: new_target,
+            effective_target: new_target,
+            signal: prev_signal.unwrap_or_default(),
+            sensors: Vec::new(),
             type_: config.type_,
-        }
+            override_remaining_ms: None,
+        })
         .into();
         Ok(msg
             .respond(status.to_string())
@@ -149,6 +275,211 @@ impl Supervisor {
             })?)
     }
 
+    /// Apply a manual signal instead of the control signal for a while, without stopping the
+    /// controller. The controller resumes with its state intact afterwards.
+    fn override_controller(&self, override_: ControllerOverride) -> Result<(), SupervisorError> {
+        let id = override_.controller_id.clone();
+        if !self.active_clients.controllers.contains_key(&id) {
+            return Err(SupervisorError::MissingController(id));
+        }
+        info(
+            self,
+            format!(
+                "Overriding controller '{}' with signal {} for {} ms",
+                id, override_.signal, override_.duration_ms
+            ),
+            "supervisor",
+        );
+        Ok(self
+            .client
+            .publish(&ControllerSubMsg::override_subject(&id), &override_.into())?)
+    }
+
+    /// Lease an actor to another client for a limited time, e.g. for manual control.
+    fn take_over_actor(
+        &mut self,
+        takeover: Takeover,
+        msg: &Message,
+    ) -> Result<(), SupervisorError> {
+        if !self.active_clients.actors.contains_key(&takeover.actor_id) {
+            return Err(SupervisorError::Missing(takeover.actor_id));
+        }
+        info(
+            self,
+            format!(
+                "Actor '{}' taken over by '{}' for {} ms",
+                takeover.actor_id, takeover.holder, takeover.duration_ms
+            ),
+            "supervisor",
+        );
+        let lease = self.leases.take_over(&takeover, TimeStamp::now());
+        self.publish_leases(vec![lease.clone()])?;
+        let reply: PubSubMsg = lease.into();
+        Ok(msg
+            .respond(reply.to_string())
+            .map_err(|err| PubSubError::Reply {
+                msg: msg.to_string(),
+                err: err.to_string(),
+            })?)
+    }
+
+    fn publish_leases(&self, leases: Vec<Lease>) -> Result<(), SupervisorError> {
+        for lease in leases {
+            self.client
+                .publish(&Lease::subject(&lease.client_id), &lease.into())?;
+        }
+        Ok(())
+    }
+
+    fn start_sequence(&mut self, config: SequenceConfig) -> Result<(), SupervisorError> {
+        let id = &config.sequence_id;
+        if self.active_clients.contatins_id(id) {
+            return Err(SupervisorError::AlreadyActive(id.clone()));
+        }
+        let input = match self.active_clients.controllers.get(&config.controller_id) {
+            Some((_, contr_config)) => contr_config.input.clone(),
+            None => return Err(SupervisorError::Missing(config.controller_id.clone())),
+        };
+        info(
+            self,
+            format!(
+                "Starting sequence '{}' with {} steps",
+                id,
+                config.steps.len()
+            ),
+            "supervisor",
+        );
+        let sequence_client = SequenceClient::new(config.clone(), &input, &self.config.nats);
+        let handle = thread::spawn(|| sequence_client.client_loop().map_err(|err| err.into()));
+        self.active_clients
+            .sequences
+            .insert(id.clone(), (handle, config));
+        Ok(())
+    }
+
+    fn command_sequence(&self, id: &ClientId, cmd: SequenceSubMsg) -> Result<(), SupervisorError> {
+        if !self.active_clients.sequences.contains_key(id) {
+            return Err(SupervisorError::Missing(id.clone()));
+        }
+        Ok(self
+            .client
+            .publish(&SequenceSubMsg::subject(id), &cmd.into())?)
+    }
+
+    /// Remove the sequences whose threads have exited, i.e. which are done.
+    fn remove_done_sequences(&mut self) {
+        let done: Vec<ClientId> = self
+            .active_clients
+            .sequences
+            .iter()
+            .filter(|(_, (handle, _))| handle.is_finished())
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in done {
+            if let Some((handle, _config)) = self.active_clients.sequences.remove(&id) {
+                match handle.join() {
+                    Ok(Ok(())) => info(self, format!("Sequence '{}' done", id), "supervisor"),
+                    Ok(Err(err)) => error(
+                        self,
+                        format!("Sequence '{}' failed: {}", id, err),
+                        "supervisor",
+                    ),
+                    Err(_) => error(
+                        self,
+                        SupervisorError::ThreadJoin(id.clone()).to_string(),
+                        "supervisor",
+                    ),
+                }
+            }
+        }
+    }
+
+    fn abort_sequence(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        let (handle, _config) = match self.active_clients.sequences.remove(id) {
+            Some(sequence) => Ok(sequence),
+            None => Err(SupervisorError::Missing(id.clone())),
+        }?;
+        info(self, format!("Aborting sequence '{}'", id), "supervisor");
+        self.stop_thread(id, handle)?;
+        Ok(())
+    }
+
+    fn fermentation_dir(&self) -> PathBuf {
+        self.config.general.state_dir.join("fermentation")
+    }
+
+    /// Restart the fermentation programs which were running when the supervisor stopped.
+    /// A program which cannot be resumed is skipped, so that it does not keep the rest of the
+    /// brewery from starting.
+    fn resume_fermentations(&mut self) -> Result<(), SupervisorError> {
+        for state in FermentationState::load_all(&self.fermentation_dir())? {
+            let res = state.map_err(SupervisorError::from).and_then(|state| {
+                info(
+                    self,
+                    format!(
+                        "Resuming fermentation program '{}'",
+                        state.config.program_id
+                    ),
+                    "supervisor",
+                );
+                self.start_fermentation(state)
+            });
+            if let Err(err) = res {
+                error(
+                    self,
+                    format!("Could not resume fermentation program: {}", err),
+                    "supervisor",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a fermentation program together with its controller, and persist its progress.
+    fn start_fermentation(&mut self, state: FermentationState) -> Result<(), SupervisorError> {
+        let id = state.config.program_id.clone();
+        if self.active_clients.contatins_id(&id) {
+            return Err(SupervisorError::AlreadyActive(id));
+        }
+        let profile = Profile::try_new(state.config.steps.clone())?;
+        let target = profile
+            .at(fermentation::elapsed(state.started, TimeStamp::now()))
+            .target;
+        let client = FermentationClient::try_new(&state, &self.config.nats)?;
+        self.start_controller(state.config.controller.clone(), target, None)?;
+        // An unsaved program would be lost at a restart, so it is not run at all.
+        if let Err(err) = state.save(&self.fermentation_dir()) {
+            self.kill_client::<ControllerPubMsg>(&state.config.controller.controller_id)?;
+            return Err(err.into());
+        }
+        info(
+            self,
+            format!("Starting fermentation program '{}'", id),
+            "supervisor",
+        );
+        let handle = thread::spawn(|| client.client_loop().map_err(|err| err.into()));
+        self.active_clients
+            .fermentations
+            .insert(id, (handle, state.config));
+        Ok(())
+    }
+
+    fn stop_fermentation(&mut self, id: &ClientId) -> Result<(), SupervisorError> {
+        let (handle, config) = match self.active_clients.fermentations.remove(id) {
+            Some(fermentation) => Ok(fermentation),
+            None => Err(SupervisorError::Missing(id.clone())),
+        }?;
+        info(
+            self,
+            format!("Stopping fermentation program '{}'", id),
+            "supervisor",
+        );
+        self.stop_thread(id, handle)?;
+        self.kill_client::<ControllerPubMsg>(&config.controller.controller_id)?;
+        FermentationState::remove(&self.fermentation_dir(), id)?;
+        Ok(())
+    }
+
     fn reply_active_clients(&self, msg: &Message) -> Result<(), PubSubError> {
         debug(self, String::from("Listing active clients"), "supervisor");
         let clients: PubSubMsg =
@@ -166,17 +497,22 @@ impl Supervisor {
             Some(contr) => Ok(contr),
             None => Err(SupervisorError::Missing(id.clone())),
         }?;
+        let report = self.stop_thread(id, handle)?;
+        let leases = self.leases.release(id, TimeStamp::now());
+        self.publish_leases(leases)?;
+        Ok(decode_nats_data::<T>(&report.data)?)
+    }
+
+    /// Ask a client thread to stop and wait for it to finish.
+    fn stop_thread(&self, id: &ClientId, handle: Handle) -> Result<Message, SupervisorError> {
         let msg = SupervisorPubMsg::KillClient {
             client_id: id.clone(),
         };
         let report = self.client.request(&msg.subject(), &msg.into())?;
         match handle.join() {
-            Ok(_) => {}
-            Err(_) => {
-                return Err(SupervisorError::ThreadJoin(id.clone()));
-            }
-        };
-        Ok(decode_nats_data::<T>(&report.data)?)
+            Ok(_) => Ok(report),
+            Err(_) => Err(SupervisorError::ThreadJoin(id.clone())),
+        }
     }
 
     fn add_logger(&mut self, config: &config::SupervisorConfig) -> Result<(), SupervisorError> {
@@ -185,6 +521,19 @@ impl Supervisor {
         self.add_misc_client(ClientId("log".into()), log_handle)
     }
 
+    /// Start the power manager before the actors, so that no request is missed.
+    fn add_power_manager(
+        &mut self,
+        budget: &PowerBudgetConfig,
+        actors: &[ActorConfig],
+        config: &NatsConfig,
+    ) -> Result<(), SupervisorError> {
+        let id = ClientId("power_manager".into());
+        let manager = PowerManager::new(id.clone(), PowerBudget::new(budget, actors), config);
+        let handle = thread::spawn(|| manager.client_loop().map_err(|err| err.into()));
+        self.add_misc_client(id, handle)
+    }
+
     fn add_sensor(
         &mut self,
         sensor_config: SensorConfig,
@@ -196,7 +545,7 @@ impl Supervisor {
             None => {
                 let sensor = SensorClient::new(
                     sensor_config.id.clone(),
-                    sensor_config.get_sensor()?,
+                    sensor_config.get_sensor(config)?,
                     config,
                 );
                 let handle = thread::spawn(|| sensor.client_loop().map_err(|err| err.into()));
@@ -217,8 +566,23 @@ impl Supervisor {
         match self.active_clients.actors.get(id) {
             Some(_) => Err(SupervisorError::AlreadyActive(id.clone())),
             None => {
-                let actor = ActorClient::new(id.clone(), actor_config.get_actor()?, config);
-                let handle = thread::spawn(|| actor.client_loop().map_err(|err| err.into()));
+                let interlocks = Interlocks::new(id, &self.config.hardware.interlocks);
+                let actor = actor_config.get_actor(&mut self.devices)?;
+                let mut actor_client = ActorClient::new(id.clone(), actor, interlocks, config)
+                    .with_safe_signal(actor_config.safe_signal)
+                    .with_lease()?;
+                if let Some(rated_power) = actor_config.rated_power {
+                    actor_client = actor_client.with_rated_power(rated_power);
+                }
+                if let Some(timeout) = actor_config.watchdog_timeout_ms {
+                    actor_client = actor_client.with_watchdog(Duration::from_millis(timeout));
+                }
+                if actor_config.budgeted_power().is_some()
+                    && self.config.hardware.power_budget.is_some()
+                {
+                    actor_client = actor_client.with_power_budget();
+                }
+                let handle = thread::spawn(|| actor_client.client_loop().map_err(|err| err.into()));
                 self.active_clients
                     .actors
                     .insert(id.clone(), (handle, actor_config));
@@ -260,6 +624,8 @@ struct ActiveClients {
     sensors: HashMap<ClientId, (Handle, SensorConfig)>,
     actors: HashMap<ClientId, (Handle, ActorConfig)>,
     controllers: HashMap<ClientId, (Handle, ControllerConfig)>,
+    sequences: HashMap<ClientId, (Handle, SequenceConfig)>,
+    fermentations: HashMap<ClientId, (Handle, FermentationConfig)>,
     misc: HashMap<ClientId, Handle>,
 }
 
@@ -269,6 +635,8 @@ impl ActiveClients {
             sensors: HashMap::new(),
             actors: HashMap::new(),
             controllers: HashMap::new(),
+            sequences: HashMap::new(),
+            fermentations: HashMap::new(),
             misc: HashMap::new(),
         }
     }
@@ -277,6 +645,8 @@ impl ActiveClients {
         self.sensors.contains_key(id)
             || self.actors.contains_key(id)
             || self.controllers.contains_key(id)
+            || self.sequences.contains_key(id)
+            || self.fermentations.contains_key(id)
             || self.misc.contains_key(id)
     }
 }
@@ -286,6 +656,8 @@ pub struct ActiveClientsList {
     sensors: HashMap<ClientId, SensorConfig>,
     actors: HashMap<ClientId, ActorConfig>,
     controllers: HashMap<ClientId, ControllerConfig>,
+    sequences: HashMap<ClientId, SequenceConfig>,
+    fermentations: HashMap<ClientId, FermentationConfig>,
     misc: Vec<ClientId>,
 }
 
@@ -307,6 +679,16 @@ impl From<&ActiveClients> for ActiveClientsList {
                 .iter()
                 .map(|(id, (_, config))| (id.clone(), config.clone()))
                 .collect(),
+            sequences: clients
+                .sequences
+                .iter()
+                .map(|(id, (_, config))| (id.clone(), config.clone()))
+                .collect(),
+            fermentations: clients
+                .fermentations
+                .iter()
+                .map(|(id, (_, config))| (id.clone(), config.clone()))
+                .collect(),
             misc: clients.misc.iter().map(|(id, _)| id).cloned().collect(),
         }
     }
@@ -322,12 +704,22 @@ pub enum SupervisorError {
     Missing(ClientId),
     #[error("'{0}' is already an active client")]
     AlreadyActive(ClientId),
+    #[error("'{0}' is not an active controller")]
+    MissingController(ClientId),
+    #[error("'{0}' and '{1}' must be in an exclusive interlock")]
+    MissingInterlock(ClientId, ClientId),
+    #[error("{0}")]
+    Lease(#[from] LeaseError),
     #[error("Control error")]
     Controller(#[from] ControllerError),
     #[error("Sensor error")]
     Sensor(#[from] SensorError),
     #[error("Actor error")]
     Actor(#[from] ActorError),
+    #[error("Hardware error: {0}")]
+    Hardware(#[from] HardwareError),
+    #[error("Fermentation error: {0}")]
+    Fermentation(#[from] FermentationError),
     #[error("Pubsub error: {0}")]
     PubSub(#[from] PubSubError),
     #[error("Concurrency error: {0}")]
@@ -1,8 +1,11 @@
-use crate::control::ControllerConfig;
+use crate::control::{pub_sub::ControllerOverride, ControllerConfig};
+use crate::fermentation::FermentationConfig;
 use crate::pub_sub::PubSubMsg;
 use crate::pub_sub::{
     nats_client::decode_nats_data, ClientId, ClientState, PubSubClient, PubSubError, Subject,
 };
+use crate::sequencer::SequenceConfig;
+use crate::supervisor::lease::Takeover;
 use crate::supervisor::{ActiveClientsList, Supervisor};
 use nats::{Message, Subscription};
 use serde::{Deserialize, Serialize};
@@ -44,6 +47,29 @@ pub enum SupervisorSubMsg {
     SwitchController { contr_data: NewContrData },
     #[serde(rename = "list_active_clients")]
     ListActiveClients,
+    #[serde(rename = "start_sequence")]
+    StartSequence { config: SequenceConfig },
+    #[serde(rename = "pause_sequence")]
+    PauseSequence { sequence_id: ClientId },
+    #[serde(rename = "resume_sequence")]
+    ResumeSequence { sequence_id: ClientId },
+    #[serde(rename = "skip_sequence_step")]
+    SkipSequenceStep { sequence_id: ClientId },
+    #[serde(rename = "abort_sequence")]
+    AbortSequence { sequence_id: ClientId },
+    #[serde(rename = "start_fermentation")]
+    StartFermentation { config: FermentationConfig },
+    #[serde(rename = "stop_fermentation")]
+    StopFermentation { program_id: ClientId },
+    /// Manual signal for the output of a controller for a limited time.
+    #[serde(rename = "override_controller")]
+    OverrideController { override_: ControllerOverride },
+    /// Lease an actor for a limited time, overriding the lease of its controller.
+    #[serde(rename = "take_over_actor")]
+    TakeOverActor { takeover: Takeover },
+    /// Start of a new brew session, on which the actors reset their stats.
+    #[serde(rename = "new_session")]
+    NewSession,
     #[serde(rename = "stop")]
     Stop,
 }
@@ -73,6 +99,43 @@ impl TryFrom<&Message> for SupervisorSubMsg {
                 Ok(SupervisorSubMsg::SwitchController { contr_data })
             }
             "command.list_active_clients" => Ok(SupervisorSubMsg::ListActiveClients),
+            "command.override_controller" => {
+                let override_: ControllerOverride = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::OverrideController { override_ })
+            }
+            "command.take_over_actor" => {
+                let takeover: Takeover = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::TakeOverActor { takeover })
+            }
+            "command.new_session" => Ok(SupervisorSubMsg::NewSession),
+            "command.start_sequence" => {
+                let config: SequenceConfig = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::StartSequence { config })
+            }
+            "command.pause_sequence" => {
+                let sequence_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::PauseSequence { sequence_id })
+            }
+            "command.resume_sequence" => {
+                let sequence_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::ResumeSequence { sequence_id })
+            }
+            "command.skip_sequence_step" => {
+                let sequence_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::SkipSequenceStep { sequence_id })
+            }
+            "command.abort_sequence" => {
+                let sequence_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::AbortSequence { sequence_id })
+            }
+            "command.start_fermentation" => {
+                let config: FermentationConfig = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::StartFermentation { config })
+            }
+            "command.stop_fermentation" => {
+                let program_id: ClientId = decode_nats_data(&msg.data)?;
+                Ok(SupervisorSubMsg::StopFermentation { program_id })
+            }
             _ => {
                 let msg: String = decode_nats_data(&msg.data)?;
                 Err(PubSubError::MessageParse(format!(
@@ -93,6 +156,34 @@ impl SupervisorSubMsg {
             SupervisorSubMsg::SwitchController { contr_data: _ } => {
                 Subject(String::from("command.switch_controller"))
             }
+            SupervisorSubMsg::StartSequence { config: _ } => {
+                Subject(String::from("command.start_sequence"))
+            }
+            SupervisorSubMsg::PauseSequence { sequence_id: _ } => {
+                Subject(String::from("command.pause_sequence"))
+            }
+            SupervisorSubMsg::ResumeSequence { sequence_id: _ } => {
+                Subject(String::from("command.resume_sequence"))
+            }
+            SupervisorSubMsg::SkipSequenceStep { sequence_id: _ } => {
+                Subject(String::from("command.skip_sequence_step"))
+            }
+            SupervisorSubMsg::AbortSequence { sequence_id: _ } => {
+                Subject(String::from("command.abort_sequence"))
+            }
+            SupervisorSubMsg::StartFermentation { config: _ } => {
+                Subject(String::from("command.start_fermentation"))
+            }
+            SupervisorSubMsg::StopFermentation { program_id: _ } => {
+                Subject(String::from("command.stop_fermentation"))
+            }
+            SupervisorSubMsg::OverrideController { override_: _ } => {
+                Subject(String::from("command.override_controller"))
+            }
+            SupervisorSubMsg::TakeOverActor { takeover: _ } => {
+                Subject(String::from("command.take_over_actor"))
+            }
+            SupervisorSubMsg::NewSession => Subject(String::from("command.new_session")),
             _ => panic!("No"),
         }
     }
@@ -107,6 +198,28 @@ impl Into<PubSubMsg> for SupervisorSubMsg {
             SupervisorSubMsg::SwitchController { contr_data } => PubSubMsg(
                 serde_json::to_string(&contr_data).expect("SupervisorSubMsg serialization error"),
             ),
+            SupervisorSubMsg::StartSequence { config } => PubSubMsg(
+                serde_json::to_string(&config).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::PauseSequence { sequence_id }
+            | SupervisorSubMsg::ResumeSequence { sequence_id }
+            | SupervisorSubMsg::SkipSequenceStep { sequence_id }
+            | SupervisorSubMsg::AbortSequence { sequence_id } => PubSubMsg(
+                serde_json::to_string(&sequence_id).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::StartFermentation { config } => PubSubMsg(
+                serde_json::to_string(&config).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::StopFermentation { program_id } => PubSubMsg(
+                serde_json::to_string(&program_id).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::OverrideController { override_ } => PubSubMsg(
+                serde_json::to_string(&override_).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::TakeOverActor { takeover } => PubSubMsg(
+                serde_json::to_string(&takeover).expect("SupervisorSubMsg serialization error"),
+            ),
+            SupervisorSubMsg::NewSession => PubSubMsg(String::new()),
             _ => todo!(),
         }
     }
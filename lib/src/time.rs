@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, Ord, PartialOrd, PartialEq, Eq)]
@@ -12,6 +13,50 @@ impl TimeStamp {
             .expect("Time went backwards");
         TimeStamp(since_the_epoch.as_millis())
     }
+
+    /// Seconds from `earlier` to `self`, negative if `earlier` is later.
+    pub fn secs_since(&self, earlier: TimeStamp) -> f32 {
+        (self.0 as f64 - earlier.0 as f64) as f32 / 1000.0
+    }
+
+    pub fn add_secs(&self, secs: f32) -> TimeStamp {
+        TimeStamp((self.0 as f64 + f64::from(secs) * 1000.0).max(0.0) as u128)
+    }
+}
+
+pub trait Clock: Send {
+    fn now(&self) -> TimeStamp;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> TimeStamp {
+        TimeStamp::now()
+    }
+}
+
+/// Clock which only moves when advanced, for simulations and deterministic tests.
+///
+/// Clones share the same time.
+#[derive(Clone)]
+pub struct ManualClock(Arc<Mutex<TimeStamp>>);
+
+impl ManualClock {
+    pub fn new(start: TimeStamp) -> Self {
+        ManualClock(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn advance(&self, secs: f32) {
+        let mut now = self.0.lock().expect("Manual clock lock poisoned");
+        *now = now.add_secs(secs);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> TimeStamp {
+        *self.0.lock().expect("Manual clock lock poisoned")
+    }
 }
 
 #[cfg(test)]
@@ -20,6 +65,16 @@ mod test {
     use chrono::prelude::*;
     use std::convert::TryFrom;
 
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(TimeStamp(1000));
+        let shared = clock.clone();
+        shared.advance(1.5);
+        assert_eq!(clock.now(), TimeStamp(2500));
+        assert!((clock.now().secs_since(TimeStamp(1000)) - 1.5).abs() < 1e-6);
+        assert!((TimeStamp(1000).secs_since(clock.now()) + 1.5).abs() < 1e-6);
+    }
+
     #[test]
     fn test_reversibility() {
         use chrono::Utc;
@@ -19,8 +19,15 @@ fn config_file_from_args(config_file: &Path) -> Result<SupervisorConfig, Supervi
 fn main() -> Result<(), SupervisorError> {
     let opt = Opt::from_args();
     match opt {
-        Opt::Run { config_file } => {
-            let config = config_file_from_args(config_file.as_path())?;
+        Opt::Run {
+            config_file,
+            dry_run,
+        } => {
+            let mut config = config_file_from_args(config_file.as_path())?;
+            if dry_run {
+                println!("Dry run, no pins are touched");
+                config = config.dry_run();
+            }
             println!("Starting nats");
             let mut nats_server_child = run_nats_server(&config.nats)?;
             println!("Starting supervisor");
@@ -38,5 +45,10 @@ fn main() -> Result<(), SupervisorError> {
 pub enum Opt {
     ///Run supervisor
     #[structopt(name = "run")]
-    Run { config_file: PathBuf },
+    Run {
+        config_file: PathBuf,
+        /// Use dummy GPIO pins instead of the configured backend
+        #[structopt(long)]
+        dry_run: bool,
+    },
 }